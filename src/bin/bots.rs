@@ -0,0 +1,26 @@
+use bevy::log::tracing_subscriber::{self, EnvFilter};
+use iso::bots::{self, Config};
+use std::env;
+use std::time::Duration;
+
+// Load test a room with headless players, e.g. `DEV=true BOTS=50 cargo run --bin bots`
+fn main() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+
+    let mut config = Config::default();
+    if let Some(count) = env::var("BOTS").ok().and_then(|count| count.parse().ok()) {
+        config.count = count;
+    }
+    if let Ok(room) = env::var("ROOM") {
+        config.room = room;
+    }
+    if let Some(secs) = env::var("DURATION_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+    {
+        config.duration = Some(Duration::from_secs(secs));
+    }
+
+    bots::run(config);
+}
@@ -0,0 +1,16 @@
+use bevy::log::prelude::*;
+use bevy::log::tracing_subscriber::{self, EnvFilter};
+use iso::dev_server::{self, DEV_SERVER_ADDRESS};
+use std::env;
+
+// Run a local game server, e.g. `cargo run --bin dev_server`, then start clients with DEV=true
+#[tokio::main]
+async fn main() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+
+    let address = env::var("ADDRESS").unwrap_or_else(|_| DEV_SERVER_ADDRESS.to_string());
+    if let Err(e) = dev_server::run(&address).await {
+        error!("dev server stopped: {e}");
+    }
+}
@@ -0,0 +1,310 @@
+use crate::helpers::names::generate_valid_username;
+use crate::player::path::MovePath;
+use crate::player::player::Player;
+use crate::player::store::PlayerStore;
+use crate::player::systems::{BROADCAST_THROTTLE_MS, PLAYER_SIZE};
+use crate::socket::client::{SocketEvent, SocketStatus};
+use crate::socket::reconnect::ReconnectPolicy;
+use crate::socket::reply::ReplyError;
+use crate::socket::request::Request;
+use crate::socket::response::Response;
+use crate::socket::{apply_response, Config as SocketConfig, Socket};
+use crate::socket::{GAME_ROOM, HEARTBEAT_INTERVAL_SECS};
+use crate::terrain::tile_map::TileMap;
+use crate::terrain::TerrainSource;
+use bevy::log::prelude::*;
+use bevy::math::Vec3;
+use bevy::utils::HashSet;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// This module contains headless bots for load testing a room without a window.
+/// Each bot owns a `Socket` and a `PlayerStore`, joins the room, walks paths between random
+/// spots on the terrain and sends a `player_update` every `BROADCAST_THROTTLE_MS`, like a
+/// player who clicked somewhere to move.
+/// Send/receive rates and presence convergence are logged every `report_interval`.
+
+pub const DEFAULT_BOT_COUNT: usize = 10;
+const REPORT_INTERVAL_SECS: u64 = 5;
+// Units per second, a bit under the default player speed in `Movement`
+const BOT_SPEED: f32 = 1.5;
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub count: usize,
+    pub room: String,
+    // Stop after this long, None = run until killed
+    pub duration: Option<Duration>,
+    pub report_interval: Duration,
+    pub socket: SocketConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            count: DEFAULT_BOT_COUNT,
+            room: GAME_ROOM.to_string(),
+            duration: None,
+            report_interval: Duration::from_secs(REPORT_INTERVAL_SECS),
+            // Every bot has its own runtime, so keep them small
+            socket: SocketConfig {
+                worker_threads: Some(1),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+struct Bot {
+    socket: Socket,
+    store: PlayerStore,
+    room: String,
+    path: MovePath,
+    reconnect_policy: ReconnectPolicy,
+    sent: usize,
+    received: usize,
+}
+
+impl Bot {
+    fn new(config: &Config, tile_map: &TileMap) -> Self {
+        let mut socket = Socket::new(config.socket.clone());
+        socket.rooms = [config.room.clone()].into_iter().collect();
+
+        let mut player = Player::new(generate_valid_username());
+        player.position = Some(random_position(tile_map));
+
+        Self {
+            socket,
+            store: PlayerStore::new(player),
+            room: config.room.clone(),
+            path: MovePath::default(),
+            reconnect_policy: config.socket.reconnect_policy.clone(),
+            sent: 0,
+            received: 0,
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.socket.status == Some(SocketStatus::Connected)
+    }
+
+    // Drain every event the socket has queued since the last tick
+    fn handle_socket_events(&mut self) {
+        while let Some(socket_event) = self.socket.next_event() {
+            match socket_event {
+                SocketEvent::Close => self
+                    .socket
+                    .handle_connection_lost(SocketStatus::Closed, &self.reconnect_policy),
+                SocketEvent::Connect => self.socket.handle_connect(&mut self.store),
+                SocketEvent::ConnectFail => self
+                    .socket
+                    .handle_connection_lost(SocketStatus::ConnectFailed, &self.reconnect_policy),
+                SocketEvent::Disconnect => self
+                    .socket
+                    .handle_connection_lost(SocketStatus::Disconnected, &self.reconnect_policy),
+                SocketEvent::Reconnect => self.socket.handle_reconnect(),
+                SocketEvent::Reply(reply) => match reply.result {
+                    Err(ReplyError::Rejected { status, response }) => {
+                        warn!("{} rejected ({status}): {response}", reply.request.event())
+                    }
+                    Err(ReplyError::Timeout) => debug!("{} timed out", reply.request.event()),
+                    Ok(_) => (),
+                },
+                SocketEvent::Response(response) => {
+                    self.received += 1;
+                    apply_response(&mut self.store, response, |position| position);
+                }
+            }
+        }
+
+        for player_update in self.socket.overflow.drain() {
+            self.received += 1;
+            apply_response(
+                &mut self.store,
+                Response::PlayerUpdate(player_update),
+                |position| position,
+            );
+        }
+    }
+
+    // Step along the current path, finding a new one once it is finished or blocked
+    fn wander(&mut self, delta: Duration, tile_map: &TileMap) -> Vec3 {
+        let position = self.store.get_player().position.unwrap_or_default();
+        let step = BOT_SPEED * delta.as_secs_f32();
+
+        // Slide along walls like players do
+        let new_position = match self.path.advance(position, step) {
+            Some(to) => tile_map.constrain_move(position, to, PLAYER_SIZE / 2.0),
+            None => position,
+        };
+        if self.path.is_empty() || new_position == position {
+            // Unreachable destinations leave the path empty, so another is tried next tick
+            self.path = MovePath::find(tile_map, new_position, random_position(tile_map))
+                .unwrap_or_default();
+        }
+
+        let player_uuid = self.store.player_uuid.clone();
+        self.store.update_player_position(player_uuid, new_position);
+        new_position
+    }
+
+    fn send_player_update(&mut self, position: Vec3) {
+        let request = Request::new_player_update(
+            self.room.clone(),
+            self.store.player_uuid.clone(),
+            position,
+            self.sent as u32 + 1,
+        );
+        match self.socket.handle.call(request) {
+            Ok(_) => self.sent += 1,
+            Err(e) => debug!("player_update request error: {e:?}"),
+        }
+    }
+
+    fn send_heartbeat(&mut self) {
+        if let Err(e) = self.socket.handle.call(Request::new_heartbeat()) {
+            debug!("heartbeat request error: {e:?}");
+        }
+    }
+
+    // How many of the other bots this bot has seen join the room
+    fn seen_bots(&self, bot_uuids: &HashSet<String>) -> usize {
+        bot_uuids
+            .iter()
+            .filter(|uuid| !self.store.is_player_self(uuid))
+            .filter(|uuid| self.store.players.contains_key(*uuid))
+            .count()
+    }
+}
+
+// Totals at the time of the previous report, used to compute rates
+struct Report {
+    reported_at: Instant,
+    sent: usize,
+    received: usize,
+    converged_at: Option<Duration>,
+}
+
+impl Report {
+    fn new() -> Self {
+        Self {
+            reported_at: Instant::now(),
+            sent: 0,
+            received: 0,
+            converged_at: None,
+        }
+    }
+
+    fn log(&mut self, bots: &[Bot], started_at: Instant) {
+        let elapsed = self.reported_at.elapsed().as_secs_f32().max(f32::EPSILON);
+        let sent: usize = bots.iter().map(|bot| bot.sent).sum();
+        let received: usize = bots.iter().map(|bot| bot.received).sum();
+
+        let overflowed: usize = bots.iter().map(|bot| bot.socket.metrics.overflowed()).sum();
+        let connected = bots.iter().filter(|bot| bot.is_connected()).count();
+        let (converged, seen_percent) = presence_convergence(bots);
+
+        info!(
+            "connected={connected}/{} sent={:.1}/s received={:.1}/s overflow={overflowed} converged={converged}/{} seen={seen_percent:.1}%",
+            bots.len(),
+            (sent - self.sent) as f32 / elapsed,
+            (received - self.received) as f32 / elapsed,
+            bots.len(),
+        );
+
+        if self.converged_at.is_none() && converged == bots.len() {
+            let converged_at = started_at.elapsed();
+            info!("presence converged after {converged_at:.2?}");
+            self.converged_at = Some(converged_at);
+        }
+
+        self.reported_at = Instant::now();
+        self.sent = sent;
+        self.received = received;
+    }
+}
+
+// Returns how many bots have seen every other bot, and the percentage of bots seen overall
+fn presence_convergence(bots: &[Bot]) -> (usize, f32) {
+    let bot_uuids: HashSet<String> = bots
+        .iter()
+        .map(|bot| bot.store.player_uuid.clone())
+        .collect();
+    let others = bots.len().saturating_sub(1);
+    if others == 0 {
+        return (bots.len(), 100.0);
+    }
+
+    let seen: Vec<usize> = bots.iter().map(|bot| bot.seen_bots(&bot_uuids)).collect();
+    let converged = seen.iter().filter(|&&seen| seen == others).count();
+    let seen_percent = seen.iter().sum::<usize>() as f32 / (bots.len() * others) as f32 * 100.0;
+
+    (converged, seen_percent)
+}
+
+fn random_position(tile_map: &TileMap) -> Vec3 {
+    tile_map
+        .random_walkable_position(PLAYER_SIZE / 2.0)
+        .unwrap_or(Vec3::Y * PLAYER_SIZE / 2.0)
+}
+
+// Run the bots until the configured duration is up, blocking the current thread
+pub fn run(config: Config) {
+    info!("spawning {} bots in room {}", config.count, config.room);
+
+    // Bots walk the same terrain as players in the room, so the server accepts their moves
+    let tile_map = TerrainSource::default().build(&config.room);
+    let mut bots: Vec<Bot> = (0..config.count)
+        .map(|_| Bot::new(&config, &tile_map))
+        .collect();
+
+    let tick = Duration::from_millis(BROADCAST_THROTTLE_MS);
+    let heartbeat_interval = Duration::from_secs_f32(HEARTBEAT_INTERVAL_SECS);
+    let started_at = Instant::now();
+    let mut last_tick_at = started_at;
+    let mut last_heartbeat_at = started_at;
+    let mut report = Report::new();
+
+    loop {
+        let tick_started_at = Instant::now();
+        let delta = tick_started_at - last_tick_at;
+        last_tick_at = tick_started_at;
+
+        let send_heartbeat = last_heartbeat_at.elapsed() >= heartbeat_interval;
+        if send_heartbeat {
+            last_heartbeat_at = tick_started_at;
+        }
+
+        for bot in bots.iter_mut() {
+            bot.handle_socket_events();
+            if !bot.is_connected() {
+                continue;
+            }
+
+            let position = bot.wander(delta, &tile_map);
+            bot.send_player_update(position);
+            if send_heartbeat {
+                bot.send_heartbeat();
+            }
+        }
+
+        if report.reported_at.elapsed() >= config.report_interval {
+            report.log(&bots, started_at);
+        }
+
+        if let Some(duration) = config.duration {
+            if started_at.elapsed() >= duration {
+                break;
+            }
+        }
+
+        thread::sleep(tick.saturating_sub(tick_started_at.elapsed()));
+    }
+
+    report.log(&bots, started_at);
+    for bot in bots {
+        if let Err(e) = bot.socket.handle.close(None) {
+            debug!("error closing bot socket: {e:?}");
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use super::collider::{Collider, Static};
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+
+/// This module contains the broad phase of collision detection, a uniform grid over the ground.
+/// Every collider is kept in the cells its bounds cover, and only moves between cells when its
+/// `GlobalTransform` or `Collider` changes. Colliders can only touch if they share a cell, so
+/// the narrow phase tests those pairs instead of every pair. Pairs of `Static` colliders are
+/// never tested, since they can't have moved into each other.
+
+// A tile wide by default, comfortably bigger than a player
+const DEFAULT_CELL_SIZE: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug)]
+struct Bounds {
+    min: Vec3,
+    max: Vec3,
+    is_static: bool,
+}
+
+impl Bounds {
+    fn overlaps(&self, other: &Bounds) -> bool {
+        self.min.cmple(other.max).all() && self.max.cmpge(other.min).all()
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct SpatialHash {
+    cell_size: f32,
+    cells: HashMap<IVec2, Vec<Entity>>,
+    bounds: HashMap<Entity, Bounds>,
+}
+
+impl Default for SpatialHash {
+    fn default() -> Self {
+        Self::new(DEFAULT_CELL_SIZE)
+    }
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            bounds: HashMap::new(),
+        }
+    }
+
+    // Add an entity with the box between min and max, replacing where it was before
+    pub fn insert(&mut self, entity: Entity, min: Vec3, max: Vec3, is_static: bool) {
+        self.remove(entity);
+        for cell in self.cells_between(min, max) {
+            self.cells.entry(cell).or_default().push(entity);
+        }
+        self.bounds.insert(
+            entity,
+            Bounds {
+                min,
+                max,
+                is_static,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        let Some(bounds) = self.bounds.remove(&entity) else {
+            return;
+        };
+
+        for cell in self.cells_between(bounds.min, bounds.max) {
+            if let Some(entities) = self.cells.get_mut(&cell) {
+                entities.retain(|&other| other != entity);
+                if entities.is_empty() {
+                    self.cells.remove(&cell);
+                }
+            }
+        }
+    }
+
+    // Entities whose bounds overlap the box between min and max
+    pub fn query(&self, min: Vec3, max: Vec3) -> HashSet<Entity> {
+        let area = Bounds {
+            min,
+            max,
+            is_static: false,
+        };
+
+        self.cells_between(min, max)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .filter(|entity| self.bounds[*entity].overlaps(&area))
+            .copied()
+            .collect()
+    }
+
+    // Every pair of entities with overlapping bounds, once and ordered, skipping static pairs
+    pub fn pairs(&self) -> HashSet<(Entity, Entity)> {
+        let mut pairs = HashSet::new();
+
+        for entities in self.cells.values() {
+            for (i, &a) in entities.iter().enumerate() {
+                for &b in &entities[i + 1..] {
+                    let pair = if a < b { (a, b) } else { (b, a) };
+                    if pairs.contains(&pair) {
+                        continue;
+                    }
+
+                    let (a_bounds, b_bounds) = (&self.bounds[&a], &self.bounds[&b]);
+                    if (a_bounds.is_static && b_bounds.is_static) || !a_bounds.overlaps(b_bounds) {
+                        continue;
+                    }
+                    pairs.insert(pair);
+                }
+            }
+        }
+
+        pairs
+    }
+
+    fn cells_between(&self, min: Vec3, max: Vec3) -> impl Iterator<Item = IVec2> {
+        let min = (min.xz() / self.cell_size).floor().as_ivec2();
+        let max = (max.xz() / self.cell_size).floor().as_ivec2();
+        (min.y..=max.y).flat_map(move |z| (min.x..=max.x).map(move |x| IVec2::new(x, z)))
+    }
+}
+
+// Move colliders that changed since the last frame to their new cells
+pub fn update_spatial_hash(
+    mut spatial_hash: ResMut<SpatialHash>,
+    collider_query: Query<
+        (Entity, &GlobalTransform, &Collider, Has<Static>),
+        Or<(Changed<GlobalTransform>, Changed<Collider>)>,
+    >,
+    mut removed: RemovedComponents<Collider>,
+) {
+    for entity in removed.read() {
+        spatial_hash.remove(entity);
+    }
+
+    for (entity, transform, collider, is_static) in collider_query.iter() {
+        let (min, max) = collider.world(transform).bounds();
+        spatial_hash.insert(entity, min, max, is_static);
+    }
+}
@@ -0,0 +1,485 @@
+use bevy::prelude::*;
+use bevy::render::primitives::Aabb;
+
+/// This module contains collision shapes and the narrow phase tests between them.
+/// A `Collider` is given in an entity's local space and placed in the world with its
+/// `GlobalTransform`, so shapes turn with the entity and a unit shape fits a unit mesh.
+/// Entities with a `MeshCollider` get a box fitted to their mesh's `Aabb` instead.
+/// In the world, boxes are oriented boxes tested with the separating axis theorem, and spheres
+/// and capsules are spheres swept along a segment. `contact` finds how far, and in which
+/// direction, one shape has to move to stop overlapping another.
+
+// Steps of the searches along a segment, each narrowing it down by a third
+const SEGMENT_SEARCH_STEPS: usize = 24;
+// Cross product axes this short come from parallel edges and can't separate anything
+const MIN_AXIS_LENGTH: f32 = 1e-6;
+
+#[derive(Component, Clone, Copy, Debug, PartialEq, Reflect)]
+pub enum Collider {
+    // Box around center, turning with the entity
+    Cuboid { center: Vec3, half_size: Vec3 },
+    Sphere { radius: f32 },
+    // Capsule along the entity's y axis, half_height is from its center to either cap's center
+    Capsule { radius: f32, half_height: f32 },
+}
+
+impl Collider {
+    // Box fitting a cuboid mesh of the given size
+    pub fn cuboid(size: Vec3) -> Self {
+        Self::Cuboid {
+            center: Vec3::ZERO,
+            half_size: size / 2.0,
+        }
+    }
+
+    // Shape in the world for an entity with this GlobalTransform. Boxes stretch exactly, round
+    // shapes grow to fit the largest scale they are stretched by.
+    pub fn world(self, transform: &GlobalTransform) -> Shape {
+        let (scale, rotation, translation) = transform.to_scale_rotation_translation();
+        let scale = scale.abs();
+
+        match self {
+            Self::Cuboid { center, half_size } => Shape::Obb {
+                center: transform.transform_point(center),
+                rotation,
+                half_size: half_size * scale,
+            },
+            Self::Sphere { radius } => Shape::Capsule {
+                a: translation,
+                b: translation,
+                radius: radius * scale.max_element(),
+            },
+            Self::Capsule {
+                radius,
+                half_height,
+            } => {
+                let scaled_radius = radius * scale.x.max(scale.z);
+                let half_extent = (half_height + radius) * scale.y;
+                let axis = rotation * Vec3::Y * (half_extent - scaled_radius).max(0.0);
+                Shape::Capsule {
+                    a: translation - axis,
+                    b: translation + axis,
+                    radius: scaled_radius,
+                }
+            }
+        }
+    }
+}
+
+impl From<&Aabb> for Collider {
+    fn from(aabb: &Aabb) -> Self {
+        Self::Cuboid {
+            center: aabb.center.into(),
+            half_size: aabb.half_extents.into(),
+        }
+    }
+}
+
+// Entities whose collider is a box fitted to their mesh, once its bounds are computed
+#[derive(Component, Debug, Default)]
+pub struct MeshCollider;
+
+// Colliders that never move, like the terrain's, which are only tested against moving ones
+#[derive(Component, Debug, Default)]
+pub struct Static;
+
+// Colliders that detect the local player walking into them instead of blocking it
+#[derive(Component, Debug, Default)]
+pub struct TriggerZone;
+
+// Collider placed in the world
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+    Obb {
+        center: Vec3,
+        rotation: Quat,
+        half_size: Vec3,
+    },
+    // Sphere swept from a to b, just a sphere when they are the same
+    Capsule {
+        a: Vec3,
+        b: Vec3,
+        radius: f32,
+    },
+}
+
+impl Shape {
+    // Corners of the axis-aligned box around the shape
+    pub fn bounds(self) -> (Vec3, Vec3) {
+        match self {
+            Self::Obb {
+                center,
+                rotation,
+                half_size,
+            } => {
+                let axes = Mat3::from_quat(rotation);
+                let half_extents = axes.x_axis.abs() * half_size.x
+                    + axes.y_axis.abs() * half_size.y
+                    + axes.z_axis.abs() * half_size.z;
+                (center - half_extents, center + half_extents)
+            }
+            Self::Capsule { a, b, radius } => (a.min(b) - radius, a.max(b) + radius),
+        }
+    }
+
+    pub fn translated(self, offset: Vec3) -> Self {
+        match self {
+            Self::Obb {
+                center,
+                rotation,
+                half_size,
+            } => Self::Obb {
+                center: center + offset,
+                rotation,
+                half_size,
+            },
+            Self::Capsule { a, b, radius } => Self::Capsule {
+                a: a + offset,
+                b: b + offset,
+                radius,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Contact {
+    // Direction to move the first shape in to separate it from the second
+    pub normal: Vec3,
+    // How far the shapes overlap along the normal
+    pub depth: f32,
+}
+
+impl Contact {
+    fn flip(self) -> Self {
+        Self {
+            normal: -self.normal,
+            ..self
+        }
+    }
+}
+
+// Overlap between two shapes, None if they are apart
+pub fn contact(a: Shape, b: Shape) -> Option<Contact> {
+    match (a, b) {
+        (
+            Shape::Obb {
+                center: a_center,
+                rotation: a_rotation,
+                half_size: a_half,
+            },
+            Shape::Obb {
+                center: b_center,
+                rotation: b_rotation,
+                half_size: b_half,
+            },
+        ) => obb_obb(
+            (a_center, Mat3::from_quat(a_rotation), a_half),
+            (b_center, Mat3::from_quat(b_rotation), b_half),
+        ),
+        (
+            Shape::Obb {
+                center,
+                rotation,
+                half_size,
+            },
+            Shape::Capsule { a, b, radius },
+        ) => obb_capsule(center, rotation, half_size, (a, b), radius).map(Contact::flip),
+        (
+            Shape::Capsule { a, b, radius },
+            Shape::Obb {
+                center,
+                rotation,
+                half_size,
+            },
+        ) => obb_capsule(center, rotation, half_size, (a, b), radius),
+        (
+            Shape::Capsule {
+                a: a_start,
+                b: a_end,
+                radius: a_radius,
+            },
+            Shape::Capsule {
+                a: b_start,
+                b: b_end,
+                radius: b_radius,
+            },
+        ) => {
+            let (a_point, b_point) = closest_between_segments((a_start, a_end), (b_start, b_end));
+            separate(a_point - b_point, a_radius + b_radius)
+        }
+    }
+}
+
+// Separating axis test between two oriented boxes given as center, axes and half size.
+// The boxes overlap unless their shadows are apart on one of the 15 candidate axes, and the
+// axis they overlap least on is the one to push apart along.
+fn obb_obb(
+    (a_center, a_axes, a_half): (Vec3, Mat3, Vec3),
+    (b_center, b_axes, b_half): (Vec3, Mat3, Vec3),
+) -> Option<Contact> {
+    let a_axes = [a_axes.x_axis, a_axes.y_axis, a_axes.z_axis];
+    let b_axes = [b_axes.x_axis, b_axes.y_axis, b_axes.z_axis];
+    let offset = a_center - b_center;
+
+    let face_axes = a_axes.into_iter().chain(b_axes);
+    let edge_axes = a_axes
+        .into_iter()
+        .flat_map(|a_axis| b_axes.map(|b_axis| a_axis.cross(b_axis)))
+        .filter(|axis| axis.length() > MIN_AXIS_LENGTH)
+        .map(Vec3::normalize);
+
+    let mut best: Option<Contact> = None;
+    for axis in face_axes.chain(edge_axes) {
+        let distance = offset.dot(axis);
+        let overlap =
+            project(axis, a_axes, a_half) + project(axis, b_axes, b_half) - distance.abs();
+        if overlap <= 0.0 {
+            return None;
+        }
+
+        // Face axes come first and win ties, so boxes side by side push apart squarely
+        if !best.is_some_and(|best| overlap >= best.depth) {
+            best = Some(Contact {
+                normal: axis * distance.signum(),
+                depth: overlap,
+            });
+        }
+    }
+
+    best
+}
+
+// Half the length of a box's shadow on an axis
+fn project(axis: Vec3, box_axes: [Vec3; 3], half_size: Vec3) -> f32 {
+    box_axes[0].dot(axis).abs() * half_size.x
+        + box_axes[1].dot(axis).abs() * half_size.y
+        + box_axes[2].dot(axis).abs() * half_size.z
+}
+
+// Contact pushing the capsule out of the box, worked out in the box's own space
+fn obb_capsule(
+    center: Vec3,
+    rotation: Quat,
+    half_size: Vec3,
+    (start, end): (Vec3, Vec3),
+    radius: f32,
+) -> Option<Contact> {
+    let inverse = rotation.inverse();
+    let start = inverse * (start - center);
+    let end = inverse * (end - center);
+    let at = |t: f32| start.lerp(end, t);
+    let to_box = |point: Vec3| point - point.clamp(-half_size, half_size);
+    let face_distance = |point: Vec3| half_size - point.abs();
+
+    let offset = to_box(at(search_segment(|t| to_box(at(t)).length_squared())));
+    if offset != Vec3::ZERO {
+        return separate(offset, radius).map(|contact| Contact {
+            normal: rotation * contact.normal,
+            ..contact
+        });
+    }
+
+    // The segment reaches inside the box, so push its deepest point out through the nearest
+    // face. How deep a point is only rises then falls along the segment too.
+    let point = at(search_segment(|t| -face_distance(at(t)).min_element()));
+    let face_distance = face_distance(point);
+    let axis = least_axis(face_distance);
+    Some(Contact {
+        normal: rotation * (axis * point.dot(axis).signum()),
+        depth: face_distance.dot(axis) + radius,
+    })
+}
+
+// Where along a segment, from 0 at its start to 1 at its end, a value that only falls then
+// rises is lowest, found by repeatedly dropping the third of the segment where it is higher
+fn search_segment(value: impl Fn(f32) -> f32) -> f32 {
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0..SEGMENT_SEARCH_STEPS {
+        let first = low + (high - low) / 3.0;
+        let second = high - (high - low) / 3.0;
+        if value(first) <= value(second) {
+            high = second;
+        } else {
+            low = first;
+        }
+    }
+
+    (low + high) / 2.0
+}
+
+fn closest_on_segment(point: Vec3, (start, end): (Vec3, Vec3)) -> Vec3 {
+    let direction = end - start;
+    let length_squared = direction.length_squared();
+    if length_squared <= f32::EPSILON {
+        return start;
+    }
+
+    let t = ((point - start).dot(direction) / length_squared).clamp(0.0, 1.0);
+    start + direction * t
+}
+
+// Closest points between two segments, one on each
+fn closest_between_segments(
+    (a_start, a_end): (Vec3, Vec3),
+    (b_start, b_end): (Vec3, Vec3),
+) -> (Vec3, Vec3) {
+    let a_direction = a_end - a_start;
+    let b_direction = b_end - b_start;
+    let a_length_squared = a_direction.length_squared();
+    let b_length_squared = b_direction.length_squared();
+
+    // A segment that is just a point only needs the closest point on the other
+    if a_length_squared <= f32::EPSILON {
+        return (a_start, closest_on_segment(a_start, (b_start, b_end)));
+    }
+    if b_length_squared <= f32::EPSILON {
+        return (closest_on_segment(b_start, (a_start, a_end)), b_start);
+    }
+
+    let offset = a_start - b_start;
+    let along = a_direction.dot(b_direction);
+    let a_offset = a_direction.dot(offset);
+    let b_offset = b_direction.dot(offset);
+    let denominator = a_length_squared * b_length_squared - along * along;
+
+    // Parallel segments have many closest pairs, so start from the start of a
+    let s = if denominator > f32::EPSILON {
+        ((along * b_offset - a_offset * b_length_squared) / denominator).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let t = (along * s + b_offset) / b_length_squared;
+
+    // Clamping t moves the closest point on b, so the one on a has to be found again
+    let (s, t) = if t < 0.0 {
+        ((-a_offset / a_length_squared).clamp(0.0, 1.0), 0.0)
+    } else if t > 1.0 {
+        (((along - a_offset) / a_length_squared).clamp(0.0, 1.0), 1.0)
+    } else {
+        (s, t)
+    };
+
+    (a_start + a_direction * s, b_start + b_direction * t)
+}
+
+// Contact between two points closer than radius, pushing along the offset between them
+fn separate(offset: Vec3, radius: f32) -> Option<Contact> {
+    let distance = offset.length();
+    if distance >= radius {
+        return None;
+    }
+
+    // Points on top of each other are pushed apart sideways
+    let normal = if distance > f32::EPSILON {
+        offset / distance
+    } else {
+        Vec3::X
+    };
+    Some(Contact {
+        normal,
+        depth: radius - distance,
+    })
+}
+
+fn least_axis(values: Vec3) -> Vec3 {
+    if values.x <= values.y && values.x <= values.z {
+        Vec3::X
+    } else if values.y <= values.z {
+        Vec3::Y
+    } else {
+        Vec3::Z
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_4;
+
+    fn obb(center: Vec3, rotation: Quat, half_size: Vec3) -> Shape {
+        Shape::Obb {
+            center,
+            rotation,
+            half_size,
+        }
+    }
+
+    fn capsule(a: Vec3, b: Vec3, radius: f32) -> Shape {
+        Shape::Capsule { a, b, radius }
+    }
+
+    fn assert_contact(contact: Option<Contact>, normal: Vec3, depth: f32) {
+        let contact = contact.expect("shapes should overlap");
+        assert!(
+            contact.normal.abs_diff_eq(normal, 1e-4),
+            "normal {} instead of {normal}",
+            contact.normal
+        );
+        assert!(
+            (contact.depth - depth).abs() < 1e-4,
+            "depth {} instead of {depth}",
+            contact.depth
+        );
+    }
+
+    #[test]
+    fn boxes_rotated_45_degrees() {
+        let a = obb(Vec3::ZERO, Quat::IDENTITY, Vec3::splat(0.5));
+        let rotated = |center| obb(center, Quat::from_rotation_y(FRAC_PI_4), Vec3::splat(0.5));
+        let diagonal = Vec3::new(1.0, 0.0, 1.0).normalize();
+
+        // Facing a's corner, apart even though their bounds overlap
+        assert_eq!(contact(a, rotated(Vec3::new(0.9, 0.0, 0.9))), None);
+
+        // Closer in, they overlap least along the diagonal
+        let center = Vec3::new(0.8, 0.0, 0.8);
+        let depth = 0.5 * 2f32.sqrt() + 0.5 - center.length();
+        assert_contact(contact(a, rotated(center)), -diagonal, depth);
+    }
+
+    #[test]
+    fn parallel_capsules() {
+        let upright = |x| capsule(Vec3::new(x, -1.0, 0.0), Vec3::new(x, 1.0, 0.0), 0.5);
+
+        assert_contact(contact(upright(0.0), upright(0.8)), -Vec3::X, 0.2);
+        assert_eq!(contact(upright(0.0), upright(1.2)), None);
+    }
+
+    #[test]
+    fn capsule_through_a_box() {
+        let cube = obb(Vec3::ZERO, Quat::IDENTITY, Vec3::ONE);
+        let through = capsule(Vec3::new(0.2, -3.0, 0.0), Vec3::new(0.2, 3.0, 0.0), 0.1);
+
+        // Pushed out sideways from its deepest point, the side nearest the center
+        assert_contact(contact(through, cube), Vec3::X, 0.9);
+    }
+
+    #[test]
+    fn capsule_through_the_end_of_a_long_box() {
+        let long_box = obb(Vec3::ZERO, Quat::IDENTITY, Vec3::new(3.0, 0.2, 0.2));
+        // Crosses the box near its end, while its point nearest the center is outside the box
+        let slanted = capsule(Vec3::new(1.9, 1.15, 0.0), Vec3::new(3.9, -0.85, 0.0), 0.05);
+
+        let contact = contact(slanted, long_box).expect("shapes should overlap");
+        assert!(contact.depth > 0.05, "depth {}", contact.depth);
+        assert!(contact.depth <= 0.25, "depth {}", contact.depth);
+    }
+
+    #[test]
+    fn normal_moves_the_first_shape_away_from_the_second() {
+        let cube = obb(Vec3::ZERO, Quat::IDENTITY, Vec3::ONE);
+        let sphere = capsule(Vec3::X * 1.2, Vec3::X * 1.2, 0.5);
+        let other_cube = obb(Vec3::X * 1.5, Quat::IDENTITY, Vec3::ONE);
+
+        assert_contact(contact(cube, sphere), -Vec3::X, 0.3);
+        assert_contact(contact(sphere, cube), Vec3::X, 0.3);
+        assert_contact(contact(cube, other_cube), -Vec3::X, 0.5);
+        assert_contact(contact(other_cube, cube), Vec3::X, 0.5);
+        assert_contact(
+            contact(sphere, sphere.translated(Vec3::X * 0.5)),
+            -Vec3::X,
+            0.5,
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use super::collider::{Collider, Shape};
+use super::{detect_collisions, Collisions};
+use crate::schedule::UpdateSet;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+
+/// This module contains a debug view of collisions, outlining every collider that overlaps
+/// another one in red.
+
+const COLLIDING_COLOR: Color = Color::RED;
+
+#[derive(Clone, Debug)]
+pub struct CollisionDebugPlugin {
+    pub enabled: bool,
+}
+
+impl Default for CollisionDebugPlugin {
+    fn default() -> Self {
+        Self { enabled: false }
+    }
+}
+
+impl Plugin for CollisionDebugPlugin {
+    fn build(&self, app: &mut App) {
+        if self.enabled {
+            app.add_systems(
+                Update,
+                draw_colliding
+                    .after(detect_collisions)
+                    .in_set(UpdateSet::AfterEffects),
+            );
+        }
+    }
+}
+
+fn draw_colliding(
+    mut gizmos: Gizmos,
+    collisions: Res<Collisions>,
+    collider_query: Query<(&GlobalTransform, &Collider)>,
+) {
+    let colliding: HashSet<Entity> = collisions.iter().flat_map(|(a, b)| [a, b]).collect();
+
+    for entity in colliding {
+        let Ok((transform, collider)) = collider_query.get(entity) else {
+            continue;
+        };
+
+        match collider.world(transform) {
+            Shape::Obb {
+                center,
+                rotation,
+                half_size,
+            } => {
+                gizmos.cuboid(
+                    Transform {
+                        translation: center,
+                        rotation,
+                        scale: half_size * 2.0,
+                    },
+                    COLLIDING_COLOR,
+                );
+            }
+            Shape::Capsule { a, b, radius } => {
+                gizmos.sphere(a, Quat::IDENTITY, radius, COLLIDING_COLOR);
+                if a == b {
+                    continue;
+                }
+
+                gizmos.sphere(b, Quat::IDENTITY, radius, COLLIDING_COLOR);
+                let (side, other_side) = (b - a).normalize().any_orthonormal_pair();
+                for offset in [side, -side, other_side, -other_side] {
+                    gizmos.line(a + offset * radius, b + offset * radius, COLLIDING_COLOR);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,313 @@
+pub mod broad_phase;
+pub mod collider;
+pub mod debug;
+
+use self::broad_phase::{update_spatial_hash, SpatialHash};
+use self::collider::{contact, Collider, MeshCollider, TriggerZone};
+use crate::player::prediction::InputHistory;
+use crate::player::systems::{
+    keep_on_terrain, update_player_position, FriendTag, PlayerTag, PlayerUpdateEvent,
+};
+use crate::schedule::UpdateSet;
+use crate::terrain::tile_map::TileMap;
+use bevy::render::primitives::Aabb;
+use bevy::render::view::VisibilitySystems;
+use bevy::transform::TransformSystem;
+use bevy::{prelude::*, utils::HashSet};
+
+/// This module contains collision between entities with a `Collider`.
+/// After the local player moves, it is pushed out of any collider it has moved into, whether
+/// that is a wall or prop from the terrain or another player. Only the local player is moved:
+/// remote players are drawn where the server says they are.
+/// Shapes turn with their entities, and `MeshCollider`s follow the mesh's own bounds, so
+/// avatars and props of any shape or rotation collide as they are drawn.
+/// Candidates are found with the `SpatialHash` broad phase, so each collider is only tested
+/// against the colliders near it.
+/// Every frame the overlapping pairs are compared with the last frame's, sending
+/// `CollisionStarted` and `CollisionEnded`. `TriggerZone` colliders don't push anything out,
+/// and send `TriggerEntered` and `TriggerExited` when the local player walks in or out of them.
+
+// Sent when two colliders start overlapping, a and b are in the same order as in `Collisions`
+#[derive(Event, Debug)]
+pub struct CollisionStarted {
+    pub a: Entity,
+    pub b: Entity,
+}
+
+impl CollisionStarted {
+    pub fn new(a: Entity, b: Entity) -> Self {
+        Self { a, b }
+    }
+}
+
+// Sent when two colliders stop overlapping, or one of them is despawned
+#[derive(Event, Debug)]
+pub struct CollisionEnded {
+    pub a: Entity,
+    pub b: Entity,
+}
+
+impl CollisionEnded {
+    pub fn new(a: Entity, b: Entity) -> Self {
+        Self { a, b }
+    }
+}
+
+#[derive(Event, Debug)]
+pub struct TriggerEntered {
+    pub zone: Entity,
+}
+
+impl TriggerEntered {
+    pub fn new(zone: Entity) -> Self {
+        Self { zone }
+    }
+}
+
+#[derive(Event, Debug)]
+pub struct TriggerExited {
+    pub zone: Entity,
+}
+
+impl TriggerExited {
+    pub fn new(zone: Entity) -> Self {
+        Self { zone }
+    }
+}
+
+// Pairs of colliders overlapping as of the last check, each pair once and ordered
+#[derive(Resource, Debug, Default)]
+pub struct Collisions {
+    pairs: HashSet<(Entity, Entity)>,
+}
+
+impl Collisions {
+    pub fn contains(&self, a: Entity, b: Entity) -> bool {
+        self.pairs.contains(&(a.min(b), a.max(b)))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, Entity)> + '_ {
+        self.pairs.iter().copied()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CollisionPlugin {}
+
+impl Default for CollisionPlugin {
+    fn default() -> Self {
+        Self {}
+    }
+}
+
+impl Plugin for CollisionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpatialHash>()
+            .init_resource::<Collisions>()
+            .add_event::<CollisionStarted>()
+            .add_event::<CollisionEnded>()
+            .add_event::<TriggerEntered>()
+            .add_event::<TriggerExited>()
+            .register_type::<Collider>()
+            // The hash is built at the end of the frame, once colliders are fitted and
+            // transforms propagated. The next frame's FixedUpdate runs before anything else
+            // moves, so push_out_player finds the other colliders where the hash has them, and
+            // takes the player's own bounds from the Transform update_player_position just set.
+            .add_systems(
+                PostUpdate,
+                (
+                    fit_mesh_colliders.after(VisibilitySystems::CalculateBounds),
+                    update_spatial_hash.after(TransformSystem::TransformPropagate),
+                )
+                    .chain(),
+            )
+            .add_systems(FixedUpdate, push_out_player.after(update_player_position))
+            .add_systems(
+                Update,
+                (detect_collisions, send_trigger_events)
+                    .chain()
+                    .in_set(UpdateSet::AfterEffects),
+            );
+    }
+}
+
+// Fit colliders to mesh bounds as soon as they are computed
+fn fit_mesh_colliders(
+    mut commands: Commands,
+    mesh_query: Query<(Entity, &Aabb), (With<MeshCollider>, Changed<Aabb>)>,
+) {
+    for (entity, aabb) in mesh_query.iter() {
+        commands.entity(entity).insert(Collider::from(aabb));
+    }
+}
+
+// Move the local player out of every collider it overlaps, staying on the terrain
+fn push_out_player(
+    mut player_query: Query<
+        (Entity, &mut Transform, &Collider),
+        (With<PlayerTag>, Without<FriendTag>),
+    >,
+    collider_query: Query<(&GlobalTransform, &Collider, Has<TriggerZone>)>,
+    spatial_hash: Res<SpatialHash>,
+    tile_map: Option<Res<TileMap>>,
+    mut event_writer: EventWriter<PlayerUpdateEvent>,
+    mut history: ResMut<InputHistory>,
+) {
+    for (player, mut transform, collider) in player_query.iter_mut() {
+        // Not the GlobalTransform, which is from the last frame like the player's hash entry
+        let shape = collider.world(&GlobalTransform::from(*transform));
+        let (min, max) = shape.bounds();
+        let mut push = Vec3::ZERO;
+
+        for entity in spatial_hash.query(min, max) {
+            if entity == player {
+                continue;
+            }
+            // Trigger zones can be walked through
+            let Ok((other_transform, other_collider, false)) = collider_query.get(entity) else {
+                continue;
+            };
+
+            let other_shape = other_collider.world(other_transform);
+            if let Some(contact) = contact(shape.translated(push), other_shape) {
+                push += contact.normal * contact.depth;
+            }
+        }
+
+        // Pushes into walls, water or ledges are dropped like any other move
+        let position = keep_on_terrain(
+            tile_map.as_deref(),
+            transform.translation,
+            transform.translation + push,
+        );
+        let delta = position - transform.translation;
+        if delta == Vec3::ZERO {
+            continue;
+        }
+        transform.translation = position;
+
+        history.record(delta, transform.translation);
+        event_writer.send(PlayerUpdateEvent::new(transform.translation));
+    }
+}
+
+// Narrow phase over the broad phase's pairs, sending events for the pairs that changed
+fn detect_collisions(
+    collider_query: Query<(&GlobalTransform, &Collider)>,
+    spatial_hash: Res<SpatialHash>,
+    mut collisions: ResMut<Collisions>,
+    mut started_writer: EventWriter<CollisionStarted>,
+    mut ended_writer: EventWriter<CollisionEnded>,
+) {
+    let pairs: HashSet<(Entity, Entity)> = spatial_hash
+        .pairs()
+        .into_iter()
+        .filter(|&(a, b)| {
+            let (Ok((transform_a, collider_a)), Ok((transform_b, collider_b))) =
+                (collider_query.get(a), collider_query.get(b))
+            else {
+                return false;
+            };
+
+            contact(collider_a.world(transform_a), collider_b.world(transform_b)).is_some()
+        })
+        .collect();
+
+    // Skip if no change, to keep change detection quiet
+    if pairs == collisions.pairs {
+        return;
+    }
+
+    for &(a, b) in pairs.difference(&collisions.pairs) {
+        started_writer.send(CollisionStarted::new(a, b));
+    }
+    for &(a, b) in collisions.pairs.difference(&pairs) {
+        ended_writer.send(CollisionEnded::new(a, b));
+    }
+    collisions.pairs = pairs;
+}
+
+// The local player and the zones it is in are remembered in pairs, so a zone is still exited
+// once it or the player is despawned and can't be queried anymore
+fn send_trigger_events(
+    mut started_reader: EventReader<CollisionStarted>,
+    mut ended_reader: EventReader<CollisionEnded>,
+    player_query: Query<(), (With<PlayerTag>, Without<FriendTag>)>,
+    zone_query: Query<(), With<TriggerZone>>,
+    mut entered_writer: EventWriter<TriggerEntered>,
+    mut exited_writer: EventWriter<TriggerExited>,
+    mut inside: Local<HashSet<(Entity, Entity)>>,
+) {
+    for &CollisionStarted { a, b } in started_reader.read() {
+        let pair = if player_query.contains(a) && zone_query.contains(b) {
+            (a, b)
+        } else if player_query.contains(b) && zone_query.contains(a) {
+            (b, a)
+        } else {
+            continue;
+        };
+        if inside.insert(pair) {
+            entered_writer.send(TriggerEntered::new(pair.1));
+        }
+    }
+    for &CollisionEnded { a, b } in ended_reader.read() {
+        if inside.remove(&(a, b)) {
+            exited_writer.send(TriggerExited::new(b));
+        } else if inside.remove(&(b, a)) {
+            exited_writer.send(TriggerExited::new(a));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_app() -> App {
+        let mut app = App::new();
+        app.add_event::<CollisionStarted>()
+            .add_event::<CollisionEnded>()
+            .add_event::<TriggerEntered>()
+            .add_event::<TriggerExited>()
+            .add_systems(Update, send_trigger_events);
+        app
+    }
+
+    fn zones<E: Event>(app: &App, zone: impl Fn(&E) -> Entity) -> Vec<Entity> {
+        let events = app.world.resource::<Events<E>>();
+        events.get_reader().read(events).map(zone).collect()
+    }
+
+    #[test]
+    fn despawned_zone_is_exited() {
+        let mut app = build_app();
+        let player = app.world.spawn(PlayerTag).id();
+        let zone = app.world.spawn(TriggerZone).id();
+
+        app.world.send_event(CollisionStarted::new(zone, player));
+        app.update();
+        assert_eq!(zones(&app, |event: &TriggerEntered| event.zone), [zone]);
+
+        // The pair ends after the zone is gone, so it can't be told apart by its components
+        app.world.despawn(zone);
+        app.world.send_event(CollisionEnded::new(zone, player));
+        app.update();
+        assert_eq!(zones(&app, |event: &TriggerExited| event.zone), [zone]);
+    }
+
+    #[test]
+    fn collisions_without_the_player_are_ignored() {
+        let mut app = build_app();
+        let zone = app.world.spawn(TriggerZone).id();
+        let prop = app.world.spawn_empty().id();
+
+        app.world.send_event(CollisionStarted::new(zone, prop));
+        app.update();
+        app.world.despawn(prop);
+        app.world.send_event(CollisionEnded::new(zone, prop));
+        app.update();
+
+        assert!(zones(&app, |event: &TriggerEntered| event.zone).is_empty());
+        assert!(zones(&app, |event: &TriggerExited| event.zone).is_empty());
+    }
+}
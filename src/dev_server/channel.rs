@@ -0,0 +1,66 @@
+use super::SessionId;
+use crate::player::player::Player;
+use crate::terrain::tile_map::TileMap;
+use serde_json::{json, Map, Value as SerdeValue};
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// This module contains the members and terrain of a `game:<room>` channel,
+/// and builds the presence payloads describing them.
+
+#[derive(Clone, Debug)]
+pub struct Member {
+    pub join_ref: Option<String>,
+    pub player: Player,
+    // When the player's position was last accepted, used to enforce the speed limit
+    pub moved_at: Instant,
+}
+
+#[derive(Debug)]
+pub struct Channel {
+    pub members: HashMap<SessionId, Member>,
+    // Terrain of the room, built the same way clients build it
+    pub tile_map: TileMap,
+}
+
+impl Channel {
+    pub fn new(tile_map: TileMap) -> Self {
+        Self {
+            members: HashMap::new(),
+            tile_map,
+        }
+    }
+
+    pub fn presence_state(&self) -> SerdeValue {
+        let players: Vec<&Player> = self.members.values().map(|member| &member.player).collect();
+        presences(&players)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+pub fn presence_diff(joins: &[&Player], leaves: &[&Player]) -> SerdeValue {
+    json!({ "joins": presences(joins), "leaves": presences(leaves) })
+}
+
+// Presences are keyed by player uuid, like Phoenix.Presence keyed by the tracked key
+fn presences(players: &[&Player]) -> SerdeValue {
+    let presences: Map<String, SerdeValue> = players
+        .iter()
+        .map(|player| {
+            let presence = json!({
+                "metas": [{
+                    "uuid": player.uuid,
+                    "username": player.username,
+                    "phx_ref": player.uuid,
+                }],
+                "player": player,
+            });
+            (player.uuid.clone(), presence)
+        })
+        .collect();
+
+    SerdeValue::Object(presences)
+}
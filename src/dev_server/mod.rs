@@ -0,0 +1,22 @@
+pub mod channel;
+pub mod movement;
+pub mod server;
+pub mod session;
+
+use self::server::DevServer;
+use bevy::log::prelude::*;
+
+/// This module contains a small Phoenix-compatible WebSocket server for local development.
+/// It speaks the same JSON (and binary) protocol as the game server for `game:<room>` topics,
+/// so clients started with `DEV=true` can play together without network access.
+
+pub const DEV_SERVER_ADDRESS: &str = "127.0.0.1:4000";
+
+pub type SessionId = u32;
+
+pub async fn run(address: &str) -> Result<(), ezsockets::Error> {
+    let (server, _) = ezsockets::Server::create(DevServer::new);
+
+    info!("dev server listening on {address}");
+    ezsockets::tungstenite::run(server, address).await
+}
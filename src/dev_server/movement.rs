@@ -0,0 +1,33 @@
+use crate::player::systems::PLAYER_SIZE;
+use crate::terrain::tile_map::TileMap;
+use bevy::math::Vec3;
+use std::time::Duration;
+
+/// This module contains the movement rules the dev server enforces on player updates,
+/// giving clients an authoritative position to reconcile their predictions against.
+
+// Fastest a player may move in units per second, with headroom over the client's speed
+pub const MAX_SPEED: f32 = 10.0;
+// Extra distance allowed per update, so updates bunched up by network jitter aren't clamped
+const SPEED_SLACK: f32 = 0.1;
+
+// Clamp a requested position to how far the player could have moved since their last
+// accepted position, then keep it on walkable tiles like the client does
+pub fn enforce(tile_map: &TileMap, from: Option<Vec3>, elapsed: Duration, to: Vec3) -> Vec3 {
+    let Some(from) = from else {
+        return clamp_to_terrain(tile_map, to);
+    };
+
+    let max_distance = MAX_SPEED * elapsed.as_secs_f32() + SPEED_SLACK;
+    let step = to - from;
+    let to = if step.length() <= max_distance {
+        to
+    } else {
+        from + step.normalize() * max_distance
+    };
+    tile_map.constrain_move(from, to, PLAYER_SIZE / 2.0)
+}
+
+pub fn clamp_to_terrain(tile_map: &TileMap, position: Vec3) -> Vec3 {
+    tile_map.clamp(position, PLAYER_SIZE / 2.0)
+}
@@ -0,0 +1,415 @@
+use super::channel::{presence_diff, Channel, Member};
+use super::movement;
+use super::session::DevSession;
+use super::SessionId;
+use crate::player::player::Player;
+use crate::socket::message::{Message, Payload};
+use crate::socket::request::{PlayerUpdatePayload, TOPIC_PREFIX};
+use crate::socket::response::PlayerUpdate;
+use crate::terrain::TerrainSource;
+use async_trait::async_trait;
+use bevy::log::prelude::*;
+use chrono::Utc;
+use ezsockets::{CloseFrame, Error as SocketError, Request as SocketRequest, Socket};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value as SerdeValue};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+/// This module contains the `DevServer` actor, which owns all channel state.
+/// It handles the subset of the Phoenix protocol the game uses:
+///   - `heartbeat` on the `phoenix` topic
+///   - `phx_join` / `phx_leave`, with `presence_state`, `presence_diff` and `rooms_update`
+///   - `player_update` and `shout`, broadcast to the rest of the channel
+
+#[derive(Debug)]
+pub enum ServerCall {
+    Text { id: SessionId, text: String },
+    Binary { id: SessionId, bytes: Vec<u8> },
+}
+
+pub struct DevServer {
+    handle: ezsockets::Server<Self>,
+    sessions: HashMap<SessionId, ezsockets::Session<SessionId, ()>>,
+    channels: HashMap<String, Channel>,
+    next_session_id: SessionId,
+    // Same terrain the clients build, so movement is enforced against the same tiles
+    terrain: TerrainSource,
+}
+
+impl DevServer {
+    pub fn new(handle: ezsockets::Server<Self>) -> Self {
+        Self {
+            handle,
+            sessions: HashMap::new(),
+            channels: HashMap::new(),
+            next_session_id: 1,
+            terrain: TerrainSource::default(),
+        }
+    }
+
+    fn handle_message(&mut self, id: SessionId, message: Message) {
+        debug!("session {id} sent {} {}", message.topic, message.event);
+
+        let topic = message.topic.clone();
+        let event = message.event.clone();
+        match (topic.as_str(), event.as_str()) {
+            ("phoenix", "heartbeat") => self.reply(id, &message, "ok", json!({})),
+            (topic, _) if !topic.starts_with(TOPIC_PREFIX) => self.reply(
+                id,
+                &message,
+                "error",
+                json!({ "reason": "unmatched topic" }),
+            ),
+            (_, "phx_join") => self.join(id, message),
+            (_, "phx_leave") => {
+                self.leave(id, &message.topic);
+                self.reply(id, &message, "ok", json!({}));
+            }
+            (_, "player_update") => self.player_update(id, message),
+            (_, "shout") => self.shout(id, message),
+            (_, event) => {
+                let reason = format!("unknown event {event}");
+                self.reply(id, &message, "error", json!({ "reason": reason }));
+            }
+        }
+    }
+
+    fn join(&mut self, id: SessionId, message: Message) {
+        let Some(JoinPayload { mut player }) = decode_json(&message) else {
+            self.reply(id, &message, "error", json!({ "reason": "invalid player" }));
+            return;
+        };
+
+        // Rejoining the same topic replaces the previous membership
+        self.leave(id, &message.topic);
+
+        let now = Utc::now().timestamp() as u64;
+        player.joined_at = now;
+        player.updated_at = now;
+        player.spawned_at = None;
+
+        let room = message.topic.strip_prefix(TOPIC_PREFIX).unwrap_or_default();
+        let channel = self
+            .channels
+            .entry(message.topic.clone())
+            .or_insert_with(|| Channel::new(self.terrain.build(room)));
+        player.position = player
+            .position
+            .map(|position| movement::clamp_to_terrain(&channel.tile_map, position));
+        channel.members.insert(
+            id,
+            Member {
+                join_ref: message.join_ref.clone(),
+                player: player.clone(),
+                moved_at: Instant::now(),
+            },
+        );
+        let presence_state = channel.presence_state();
+
+        self.reply(
+            id,
+            &message,
+            "ok",
+            json!({ "event": "phx_join", "player": player }),
+        );
+        self.push(id, &message.topic, "presence_state", presence_state);
+        self.broadcast(
+            &message.topic,
+            "presence_diff",
+            presence_diff(&[&player], &[]),
+        );
+        self.broadcast_rooms_update();
+
+        info!("@{} joined {}", player.username, message.topic);
+    }
+
+    fn leave(&mut self, id: SessionId, topic: &str) {
+        let Some(channel) = self.channels.get_mut(topic) else {
+            return;
+        };
+        let Some(member) = channel.members.remove(&id) else {
+            return;
+        };
+        if channel.is_empty() {
+            self.channels.remove(topic);
+        }
+
+        self.broadcast(
+            topic,
+            "presence_diff",
+            presence_diff(&[], &[&member.player]),
+        );
+        self.broadcast_rooms_update();
+
+        info!("@{} left {topic}", member.player.username);
+    }
+
+    fn player_update(&mut self, id: SessionId, message: Message) {
+        let player_update = match &message.payload {
+            Payload::Binary(bytes) => PlayerUpdate::from_bytes(bytes).ok(),
+            Payload::Json(_) => decode_json::<PlayerUpdate>(&message),
+        };
+        let Some(player_update) = player_update else {
+            self.reply(id, &message, "error", json!({ "reason": "invalid update" }));
+            return;
+        };
+
+        let member = self
+            .channels
+            .get_mut(&message.topic)
+            .and_then(|channel| Some((channel.members.get_mut(&id)?, &channel.tile_map)));
+        let accepted = match member {
+            None => Err("not joined"),
+            Some((member, _)) if member.player.uuid != player_update.player_uuid => {
+                Err("not your player")
+            }
+            Some((member, tile_map)) => {
+                // Enforce the speed limit and walkable tiles, the client reconciles with this
+                let position = movement::enforce(
+                    tile_map,
+                    member.player.position,
+                    member.moved_at.elapsed(),
+                    player_update.position,
+                );
+                member.player.position = Some(position);
+                member.player.updated_at = Utc::now().timestamp() as u64;
+                member.moved_at = Instant::now();
+                Ok(position)
+            }
+        };
+        let position = match accepted {
+            Ok(position) => position,
+            Err(reason) => {
+                self.reply(id, &message, "error", json!({ "reason": reason }));
+                return;
+            }
+        };
+
+        // Relay the accepted position in the same encoding the update was sent in
+        let player_update = PlayerUpdate {
+            position,
+            ..player_update
+        };
+        let bytes = PlayerUpdatePayload {
+            player_uuid: player_update.player_uuid.clone(),
+            position,
+            seq: player_update.seq,
+        }
+        .to_bytes();
+        let payload = match (&message.payload, bytes) {
+            (Payload::Binary(_), Ok(bytes)) => Payload::Binary(bytes),
+            _ => Payload::Json(json!(player_update)),
+        };
+        self.broadcast_from(id, &message.topic, "player_update", payload);
+        self.reply(
+            id,
+            &message,
+            "ok",
+            json!({ "seq": player_update.seq, "position": position }),
+        );
+    }
+
+    fn shout(&mut self, id: SessionId, message: Message) {
+        let Some(player) = self.member_player(id, &message.topic) else {
+            self.reply(id, &message, "error", json!({ "reason": "not joined" }));
+            return;
+        };
+        let Some(ShoutPayload { message: text }) = decode_json(&message) else {
+            self.reply(id, &message, "error", json!({ "reason": "invalid shout" }));
+            return;
+        };
+
+        let shout = json!({ "player": player, "message": text, "position": player.position });
+        self.broadcast(&message.topic, "shout", shout);
+        self.reply(id, &message, "ok", json!({}));
+    }
+
+    fn member_player(&self, id: SessionId, topic: &str) -> Option<Player> {
+        let member = self.channels.get(topic)?.members.get(&id)?;
+        Some(member.player.clone())
+    }
+
+    fn reply(&self, id: SessionId, request: &Message, status: &str, response: SerdeValue) {
+        let reply = Message {
+            join_ref: request.join_ref.clone(),
+            message_ref: request.message_ref,
+            topic: request.topic.clone(),
+            event: "phx_reply".to_string(),
+            payload: Payload::Json(json!({ "status": status, "response": response })),
+        };
+        self.send(id, &reply);
+    }
+
+    // Push a message to a single member of a channel
+    fn push(&self, id: SessionId, topic: &str, event: &str, payload: SerdeValue) {
+        let join_ref = self
+            .channels
+            .get(topic)
+            .and_then(|channel| channel.members.get(&id))
+            .and_then(|member| member.join_ref.clone());
+
+        let message = Message {
+            join_ref,
+            message_ref: None,
+            topic: topic.to_string(),
+            event: event.to_string(),
+            payload: Payload::Json(payload),
+        };
+        self.send(id, &message);
+    }
+
+    fn broadcast(&self, topic: &str, event: &str, payload: SerdeValue) {
+        self.broadcast_message(None, topic, event, Payload::Json(payload));
+    }
+
+    // Broadcast to every member of a channel except the sender
+    fn broadcast_from(&self, sender: SessionId, topic: &str, event: &str, payload: Payload) {
+        self.broadcast_message(Some(sender), topic, event, payload);
+    }
+
+    fn broadcast_message(
+        &self,
+        sender: Option<SessionId>,
+        topic: &str,
+        event: &str,
+        payload: Payload,
+    ) {
+        let Some(channel) = self.channels.get(topic) else {
+            return;
+        };
+
+        let message = Message {
+            join_ref: None,
+            message_ref: None,
+            topic: topic.to_string(),
+            event: event.to_string(),
+            payload,
+        };
+
+        for id in channel.members.keys() {
+            if Some(*id) != sender {
+                self.send(*id, &message);
+            }
+        }
+    }
+
+    // Tell every connected player how many players are in each room
+    fn broadcast_rooms_update(&self) {
+        let rooms: Vec<(&str, usize)> = self
+            .channels
+            .iter()
+            .map(|(topic, channel)| {
+                let name = topic.strip_prefix(TOPIC_PREFIX).unwrap_or(topic);
+                (name, channel.members.len())
+            })
+            .collect();
+
+        for topic in self.channels.keys() {
+            self.broadcast(topic, "rooms_update", json!({ "rooms": rooms }));
+        }
+    }
+
+    fn send(&self, id: SessionId, message: &Message) {
+        let Some(session) = self.sessions.get(&id) else {
+            return;
+        };
+
+        let result = match message.payload {
+            Payload::Json(_) => message
+                .serialize_to_json_string()
+                .map(|text| session.text(text).is_ok()),
+            Payload::Binary(_) => message
+                .serialize_to_binary_broadcast()
+                .map(|bytes| session.binary(bytes).is_ok()),
+        };
+
+        match result {
+            Ok(true) => (),
+            Ok(false) => warn!("error sending {} to session {id}", message.event),
+            Err(e) => error!("error serializing {}: {e}", message.event),
+        }
+    }
+}
+
+#[async_trait]
+impl ezsockets::ServerExt for DevServer {
+    type Session = DevSession;
+    type Call = ServerCall;
+
+    async fn on_connect(
+        &mut self,
+        socket: Socket,
+        _request: SocketRequest,
+        address: SocketAddr,
+    ) -> Result<ezsockets::Session<SessionId, ()>, Option<CloseFrame>> {
+        let id = self.next_session_id;
+        self.next_session_id += 1;
+
+        let server = self.handle.clone();
+        let session = ezsockets::Session::create(
+            |session| DevSession {
+                id,
+                server,
+                session,
+            },
+            id,
+            socket,
+        );
+        self.sessions.insert(id, session.clone());
+
+        info!("session {id} connected from {address}");
+        Ok(session)
+    }
+
+    async fn on_disconnect(
+        &mut self,
+        id: SessionId,
+        _reason: Result<Option<CloseFrame>, SocketError>,
+    ) -> Result<(), SocketError> {
+        self.sessions.remove(&id);
+
+        let topics: Vec<String> = self
+            .channels
+            .iter()
+            .filter(|(_, channel)| channel.members.contains_key(&id))
+            .map(|(topic, _)| topic.clone())
+            .collect();
+        for topic in topics {
+            self.leave(id, &topic);
+        }
+
+        info!("session {id} disconnected");
+        Ok(())
+    }
+
+    async fn on_call(&mut self, call: ServerCall) -> Result<(), SocketError> {
+        let message = match call {
+            ServerCall::Text { id, text } => (id, Message::new_from_json_string(&text)),
+            ServerCall::Binary { id, bytes } => (id, Message::new_from_binary_request(&bytes)),
+        };
+
+        match message {
+            (id, Ok(message)) => self.handle_message(id, message),
+            (id, Err(e)) => warn!("skipping malformed frame from session {id}: {e}"),
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct JoinPayload {
+    player: Player,
+}
+
+#[derive(Deserialize)]
+struct ShoutPayload {
+    message: String,
+}
+
+fn decode_json<T: DeserializeOwned>(message: &Message) -> Option<T> {
+    T::deserialize(message.payload.as_json()?).ok()
+}
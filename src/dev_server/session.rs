@@ -0,0 +1,46 @@
+use super::server::{DevServer, ServerCall};
+use super::SessionId;
+use async_trait::async_trait;
+use bevy::log::prelude::*;
+use ezsockets::Error as SocketError;
+
+/// This module contains the ezsockets session for a connected client.
+/// Sessions hold no game state, every frame is relayed to the `DevServer` actor.
+
+pub struct DevSession {
+    pub id: SessionId,
+    pub server: ezsockets::Server<DevServer>,
+    pub session: ezsockets::Session<SessionId, ()>,
+}
+
+impl DevSession {
+    fn relay(&self, call: ServerCall) {
+        if let Err(e) = self.server.call(call) {
+            error!("error relaying frame from session {}: {e:?}", self.id);
+        }
+    }
+}
+
+#[async_trait]
+impl ezsockets::SessionExt for DevSession {
+    type ID = SessionId;
+    type Call = ();
+
+    fn id(&self) -> &Self::ID {
+        &self.id
+    }
+
+    async fn on_text(&mut self, text: String) -> Result<(), SocketError> {
+        self.relay(ServerCall::Text { id: self.id, text });
+        Ok(())
+    }
+
+    async fn on_binary(&mut self, bytes: Vec<u8>) -> Result<(), SocketError> {
+        self.relay(ServerCall::Binary { id: self.id, bytes });
+        Ok(())
+    }
+
+    async fn on_call(&mut self, _call: ()) -> Result<(), SocketError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,321 @@
+use crate::helpers::math::round_to_two;
+use crate::picking::{update_cursor_ray, Picking, PickingPlugin};
+use crate::schedule::{StartupSet, UpdateSet};
+use crate::terrain::chunks::TileUpdateEvent;
+use crate::terrain::level::{level_path, Level, LevelError};
+use crate::terrain::tile_map::{PropKind, Tile, TileKind, TileMap};
+use crate::terrain::{LoadedLevel, DEFAULT_LEVEL};
+use bevy::prelude::*;
+use std::fs;
+
+/// This module contains the level editor, toggled with F1 when `EditorPlugin` is enabled.
+/// While editing, the tile under the cursor is found by casting the `Picking` ray onto the
+/// `TileMap`, and clicking applies the current tool to it instead of sending click events:
+///   - 1-4 paint floor, wall, water or stairs, keeping the tile's height
+///   - 5 and 6 raise or lower the tile by HEIGHT_STEP
+///   - 7 and 8 place a rock or a tree, 9 removes the prop
+///
+/// Ctrl+S (Cmd+S on macOS) saves the map to the level file. An existing level is only
+/// overwritten by the map loaded from it, so a generated map can't replace it.
+
+const TOGGLE_KEY: KeyCode = KeyCode::F1;
+const HEIGHT_STEP: f32 = 0.1;
+const HOVER_COLOR: Color = Color::YELLOW;
+
+#[derive(Clone, Copy, Debug, PartialEq, Reflect)]
+pub enum Tool {
+    Paint(TileKind),
+    Raise,
+    Lower,
+    Prop(PropKind),
+    RemoveProp,
+}
+
+impl Tool {
+    fn from_key(key: KeyCode) -> Option<Self> {
+        match key {
+            KeyCode::Digit1 => Some(Self::Paint(TileKind::Floor)),
+            KeyCode::Digit2 => Some(Self::Paint(TileKind::Wall)),
+            KeyCode::Digit3 => Some(Self::Paint(TileKind::Water)),
+            KeyCode::Digit4 => Some(Self::Paint(TileKind::Stairs)),
+            KeyCode::Digit5 => Some(Self::Raise),
+            KeyCode::Digit6 => Some(Self::Lower),
+            KeyCode::Digit7 => Some(Self::Prop(PropKind::Rock)),
+            KeyCode::Digit8 => Some(Self::Prop(PropKind::Tree)),
+            KeyCode::Digit9 => Some(Self::RemoveProp),
+            _ => None,
+        }
+    }
+
+    fn apply(self, tile: Tile) -> Tile {
+        match self {
+            Self::Paint(kind) => Tile { kind, ..tile },
+            Self::Raise => Tile {
+                height: round_to_two(tile.height + HEIGHT_STEP),
+                ..tile
+            },
+            Self::Lower => Tile {
+                height: round_to_two(tile.height - HEIGHT_STEP),
+                ..tile
+            },
+            Self::Prop(kind) => tile.with_prop(kind),
+            Self::RemoveProp => Tile { prop: None, ..tile },
+        }
+    }
+
+    // Raising and lowering happen once per click, everything else paints while held
+    fn is_continuous(self) -> bool {
+        !matches!(self, Self::Raise | Self::Lower)
+    }
+}
+
+#[derive(Resource, Debug, Reflect)]
+#[reflect(Resource)]
+pub struct Editor {
+    pub active: bool,
+    pub tool: Tool,
+    // Level file saved to, relative to the assets directory
+    pub level: String,
+    // Tile under the cursor
+    pub hovered: Option<UVec2>,
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Self {
+            active: false,
+            tool: Tool::Paint(TileKind::Floor),
+            level: DEFAULT_LEVEL.to_string(),
+            hovered: None,
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+struct EditorInfo;
+
+#[derive(Clone, Debug)]
+pub struct EditorPlugin {
+    pub enabled: bool,
+    // Level file saved to, relative to the assets directory
+    pub level: String,
+}
+
+impl Default for EditorPlugin {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            level: DEFAULT_LEVEL.to_string(),
+        }
+    }
+}
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        if self.enabled {
+            if !app.is_plugin_added::<PickingPlugin>() {
+                app.add_plugins(PickingPlugin::default());
+            }
+
+            app.insert_resource(Editor {
+                level: self.level.clone(),
+                ..default()
+            })
+            .register_type::<Editor>()
+            .add_systems(Startup, spawn_editor_info.in_set(StartupSet::SpawnEntities))
+            .add_systems(
+                Update,
+                (
+                    toggle_editor,
+                    select_tool,
+                    hover_tile,
+                    apply_tool,
+                    save_level,
+                )
+                    .chain()
+                    .after(update_cursor_ray)
+                    .in_set(UpdateSet::UserInputEffects),
+            )
+            .add_systems(
+                Update,
+                (draw_hovered_tile, update_editor_info).in_set(UpdateSet::AfterEffects),
+            );
+        }
+    }
+}
+
+fn toggle_editor(
+    mut editor: ResMut<Editor>,
+    mut picking: ResMut<Picking>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    if keyboard_input.just_pressed(TOGGLE_KEY) {
+        editor.active = !editor.active;
+        editor.hovered = None;
+        picking.paused = editor.active;
+        info!("editor {}", if editor.active { "on" } else { "off" });
+    }
+}
+
+fn select_tool(mut editor: ResMut<Editor>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if !editor.active {
+        return;
+    }
+
+    if let Some(tool) = keyboard_input
+        .get_just_pressed()
+        .find_map(|&key| Tool::from_key(key))
+    {
+        editor.tool = tool;
+    }
+}
+
+fn hover_tile(mut editor: ResMut<Editor>, picking: Res<Picking>, tile_map: Res<TileMap>) {
+    if !editor.active {
+        return;
+    }
+
+    let hovered = picking
+        .ray
+        .and_then(|ray| tile_map.raycast(ray.origin, *ray.direction))
+        .map(|(coords, _)| coords);
+
+    // Skip if no change, to keep change detection quiet
+    if editor.hovered != hovered {
+        editor.hovered = hovered;
+    }
+}
+
+fn apply_tool(
+    editor: Res<Editor>,
+    mouse_button: Res<ButtonInput<MouseButton>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut tile_map: ResMut<TileMap>,
+    mut event_writer: EventWriter<TileUpdateEvent>,
+) {
+    // Super + drag rotates the camera
+    if !editor.active || keyboard_input.pressed(KeyCode::SuperLeft) {
+        return;
+    }
+    let Some(coords) = editor.hovered else {
+        return;
+    };
+
+    let clicked = if editor.tool.is_continuous() {
+        mouse_button.pressed(MouseButton::Left)
+    } else {
+        mouse_button.just_pressed(MouseButton::Left)
+    };
+    if !clicked {
+        return;
+    }
+
+    let Some(&tile) = tile_map.get(coords) else {
+        return;
+    };
+    let edited = editor.tool.apply(tile);
+    if edited != tile {
+        tile_map.set(coords, edited);
+        event_writer.send(TileUpdateEvent::new(coords));
+    }
+}
+
+fn save_level(
+    editor: Res<Editor>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    tile_map: Res<TileMap>,
+    loaded_level: Res<LoadedLevel>,
+) {
+    let modifier = keyboard_input.any_pressed([
+        KeyCode::ControlLeft,
+        KeyCode::ControlRight,
+        KeyCode::SuperLeft,
+        KeyCode::SuperRight,
+    ]);
+    if !editor.active || !modifier || !keyboard_input.just_pressed(KeyCode::KeyS) {
+        return;
+    }
+
+    let path = level_path(&editor.level);
+    let is_loaded_level = loaded_level.level.as_ref() == Some(&editor.level);
+    if !is_loaded_level && path.exists() {
+        warn!(
+            "not saving, the map wasn't loaded from {} and would replace it",
+            path.display()
+        );
+        return;
+    }
+
+    let result = match path.parent() {
+        Some(directory) => fs::create_dir_all(directory).map_err(LevelError::Io),
+        None => Ok(()),
+    }
+    .and_then(|_| Level::from_tile_map(&tile_map).write(&path));
+
+    match result {
+        Ok(()) => info!("saved level to {}", path.display()),
+        Err(e) => error!("failed to save level to {}: {e}", path.display()),
+    }
+}
+
+fn draw_hovered_tile(mut gizmos: Gizmos, editor: Res<Editor>, tile_map: Res<TileMap>) {
+    let Some(coords) = editor.hovered.filter(|_| editor.active) else {
+        return;
+    };
+
+    let size = tile_map.tile_size;
+    gizmos.cuboid(
+        Transform::from_translation(tile_map.tile_center(coords))
+            .with_scale(Vec3::new(size, 0.01, size)),
+        HOVER_COLOR,
+    );
+}
+
+fn spawn_editor_info(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/FiraCode-Regular.otf"),
+                font_size: 14.,
+                color: Color::ANTIQUE_WHITE,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            right: Val::Px(10.0),
+            ..default()
+        }),
+        EditorInfo,
+        Name::new("EditorInfo"),
+    ));
+}
+
+fn update_editor_info(
+    mut info_query: Query<(&mut Text, &mut Visibility), With<EditorInfo>>,
+    editor: Res<Editor>,
+) {
+    if !editor.is_changed() {
+        return;
+    }
+    let Ok((mut text, mut visibility)) = info_query.get_single_mut() else {
+        return;
+    };
+
+    *visibility = if editor.active {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
+
+    let hovered = match editor.hovered {
+        Some(coords) => format!("{},{}", coords.x, coords.y),
+        None => "-".to_string(),
+    };
+    text.sections[0].value = format!(
+        "editor: {:?} tile={hovered}\n1-4 paint, 5/6 height, 7/8 props, 9 clear, ctrl+s save",
+        editor.tool
+    );
+}
@@ -0,0 +1,13 @@
+pub mod bots;
+pub mod cameras;
+pub mod collision;
+pub mod dev_server;
+pub mod dev_tools;
+pub mod editor;
+pub mod helpers;
+pub mod lighting;
+pub mod picking;
+pub mod player;
+pub mod schedule;
+pub mod socket;
+pub mod terrain;
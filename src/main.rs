@@ -1,22 +1,16 @@
-mod cameras;
-mod collision;
-mod dev_tools;
-mod helpers;
-mod lighting;
-mod player;
-mod schedule;
-mod socket;
-mod terrain;
-
 use bevy::prelude::*;
-use cameras::CameraPlugin;
-use collision::CollisionPlugin;
-use dev_tools::DevToolsPlugin;
-use helpers::names::get_title_from_env_or_generate;
-use lighting::LightingPlugin;
-use player::PlayerPlugin;
-use socket::SocketPlugin;
-use terrain::TerrainPlugin;
+use iso::cameras::CameraPlugin;
+use iso::collision::debug::CollisionDebugPlugin;
+use iso::collision::CollisionPlugin;
+use iso::dev_tools::DevToolsPlugin;
+use iso::editor::EditorPlugin;
+use iso::helpers::names::get_title_from_env_or_generate;
+use iso::lighting::LightingPlugin;
+use iso::picking::PickingPlugin;
+use iso::player::PlayerPlugin;
+use iso::socket::SocketPlugin;
+use iso::terrain::TerrainPlugin;
+use std::env;
 
 fn main() {
     App::new()
@@ -34,5 +28,18 @@ fn main() {
         .add_plugins(TerrainPlugin::default())
         .add_plugins(PlayerPlugin::default())
         .add_plugins(CollisionPlugin::default())
+        .add_plugins(CollisionDebugPlugin {
+            enabled: env_flag("COLLISION_DEBUG"),
+        })
+        .add_plugins(PickingPlugin::default())
+        .add_plugins(EditorPlugin {
+            enabled: env_flag("LEVEL_EDITOR"),
+            ..default()
+        })
         .run();
 }
+
+// Whether an environment variable is set to "true", for opting into dev-only plugins
+fn env_flag(name: &str) -> bool {
+    env::var(name).unwrap_or_default() == "true"
+}
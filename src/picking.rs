@@ -0,0 +1,259 @@
+use crate::cameras::SceneCamera;
+use crate::player::store::PlayerStore;
+use crate::player::systems::{FriendTag, PlayerTag};
+use crate::schedule::UpdateSet;
+use crate::terrain::tile_map::TileMap;
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+
+/// This module converts the cursor position into world space.
+/// Every frame a ray is cast from the active `SceneCamera` through the cursor, which works for
+/// both the orthographic and perspective cameras. On click, the nearest player or terrain tile
+/// along the ray is picked, sending `PlayerClicked` or `TerrainClicked`.
+
+#[derive(Event, Debug)]
+pub struct TerrainClicked(pub Vec3);
+
+#[derive(Event, Debug)]
+pub struct PlayerClicked(pub String);
+
+#[derive(Resource, Debug, Default)]
+pub struct Picking {
+    // Ray from the camera through the cursor, None while the cursor is outside the window
+    pub ray: Option<Ray3d>,
+    // Tools that handle clicks themselves, like the editor, pause click events while active
+    pub paused: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pick {
+    Terrain(Vec3),
+    Player(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct PickingPlugin {}
+
+impl Default for PickingPlugin {
+    fn default() -> Self {
+        Self {}
+    }
+}
+
+impl Plugin for PickingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Picking>()
+            .add_event::<TerrainClicked>()
+            .add_event::<PlayerClicked>()
+            .add_systems(
+                Update,
+                (update_cursor_ray, send_click_events)
+                    .chain()
+                    .in_set(UpdateSet::UserInputEffects),
+            );
+    }
+}
+
+pub fn update_cursor_ray(
+    mut picking: ResMut<Picking>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<SceneCamera>>,
+) {
+    let cursor = window_query
+        .get_single()
+        .ok()
+        .and_then(Window::cursor_position);
+    let camera = camera_query.iter().find(|(camera, _)| camera.is_active);
+
+    picking.ray = cursor
+        .zip(camera)
+        .and_then(|(cursor, (camera, camera_transform))| {
+            camera.viewport_to_world(camera_transform, cursor)
+        });
+}
+
+pub fn send_click_events(
+    picking: Res<Picking>,
+    mouse_button: Res<ButtonInput<MouseButton>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    tile_map: Option<Res<TileMap>>,
+    player_query: Query<(&GlobalTransform, Option<&FriendTag>), With<PlayerTag>>,
+    store: Res<PlayerStore>,
+    mut terrain_event_writer: EventWriter<TerrainClicked>,
+    mut player_event_writer: EventWriter<PlayerClicked>,
+) {
+    // Clicks with Super or Space held rotate and pan the camera
+    let camera_gesture = keyboard_input.any_pressed([KeyCode::SuperLeft, KeyCode::Space]);
+    if picking.paused || camera_gesture || !mouse_button.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(ray) = picking.ray else {
+        return;
+    };
+
+    let players = player_query.iter().map(|(transform, friend_tag)| {
+        let player_uuid = match friend_tag {
+            Some(friend_tag) => friend_tag.player_uuid.clone(),
+            None => store.player_uuid.clone(),
+        };
+        (player_uuid, transform)
+    });
+
+    match pick(ray, tile_map.as_deref(), players) {
+        Some(Pick::Player(player_uuid)) => {
+            player_event_writer.send(PlayerClicked(player_uuid));
+        }
+        Some(Pick::Terrain(point)) => {
+            terrain_event_writer.send(TerrainClicked(point));
+        }
+        None => (),
+    }
+}
+
+// Nearest player or terrain tile along the ray
+pub fn pick<'a>(
+    ray: Ray3d,
+    tile_map: Option<&TileMap>,
+    players: impl Iterator<Item = (String, &'a GlobalTransform)>,
+) -> Option<Pick> {
+    let terrain = tile_map
+        .and_then(|tile_map| tile_map.raycast(ray.origin, *ray.direction))
+        .map(|(_, point)| (point.distance(ray.origin), Pick::Terrain(point)));
+
+    let player = players
+        .filter_map(|(player_uuid, transform)| {
+            let (scale, _, translation) = transform.to_scale_rotation_translation();
+            let distance = ray_aabb(ray, translation - scale / 2.0, translation + scale / 2.0)?;
+            Some((distance, Pick::Player(player_uuid)))
+        })
+        .min_by(|(a, _), (b, _)| a.total_cmp(b));
+
+    [terrain, player]
+        .into_iter()
+        .flatten()
+        .min_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, pick)| pick)
+}
+
+// Distance along the ray to where it enters the box, using the slab method
+pub fn ray_aabb(ray: Ray3d, min: Vec3, max: Vec3) -> Option<f32> {
+    let inverse = ray.direction.recip();
+    let t1 = (min - ray.origin) * inverse;
+    let t2 = (max - ray.origin) * inverse;
+
+    let near = t1.min(t2).max_element();
+    let far = t1.max(t2).min_element();
+    if near > far || far < 0.0 {
+        return None;
+    }
+
+    Some(near.max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ray(origin: Vec3, direction: Vec3) -> Ray3d {
+        Ray3d::new(origin, direction)
+    }
+
+    // Unit cube centered at the origin
+    fn unit_aabb(ray: Ray3d) -> Option<f32> {
+        ray_aabb(ray, Vec3::splat(-0.5), Vec3::splat(0.5))
+    }
+
+    #[test]
+    fn ray_from_outside_hits_the_near_face() {
+        let distance = unit_aabb(ray(Vec3::new(-5.0, 0.0, 0.0), Vec3::X));
+        assert_eq!(distance, Some(4.5));
+
+        // Diagonally through a corner region
+        let distance = unit_aabb(ray(Vec3::splat(3.0), -Vec3::ONE)).unwrap();
+        assert!((distance - 2.5 * 3f32.sqrt()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn ray_starting_inside_the_box_hits_at_zero() {
+        assert_eq!(unit_aabb(ray(Vec3::ZERO, Vec3::X)), Some(0.0));
+        assert_eq!(
+            unit_aabb(ray(Vec3::new(0.2, -0.3, 0.1), -Vec3::Y)),
+            Some(0.0)
+        );
+    }
+
+    #[test]
+    fn ray_missing_the_box() {
+        // Passes beside it
+        assert_eq!(unit_aabb(ray(Vec3::new(-5.0, 2.0, 0.0), Vec3::X)), None);
+        // Points away from it
+        assert_eq!(unit_aabb(ray(Vec3::new(-5.0, 0.0, 0.0), -Vec3::X)), None);
+        // Would only hit it behind the origin
+        assert_eq!(unit_aabb(ray(Vec3::new(0.0, 0.0, 5.0), Vec3::Z)), None);
+    }
+
+    #[test]
+    fn ray_parallel_to_a_slab() {
+        // Within the y and z slabs, moving along x only
+        assert_eq!(
+            unit_aabb(ray(Vec3::new(-2.0, 0.3, -0.3), Vec3::X)),
+            Some(1.5)
+        );
+        // Outside the y slab it can never enter it
+        assert_eq!(unit_aabb(ray(Vec3::new(-2.0, 0.6, 0.0), Vec3::X)), None);
+        assert_eq!(unit_aabb(ray(Vec3::new(0.0, 0.0, -2.0), Vec3::X)), None);
+    }
+
+    #[test]
+    fn pick_returns_the_nearest_player() {
+        let transforms = [
+            (
+                "far",
+                GlobalTransform::from_translation(Vec3::new(10.0, 0.5, 0.0)),
+            ),
+            (
+                "near",
+                GlobalTransform::from_translation(Vec3::new(4.0, 0.5, 0.0)),
+            ),
+            (
+                "aside",
+                GlobalTransform::from_translation(Vec3::new(2.0, 0.5, 3.0)),
+            ),
+        ];
+        let players = || {
+            transforms
+                .iter()
+                .map(|(player_uuid, transform)| (player_uuid.to_string(), transform))
+        };
+        let ray = ray(Vec3::new(0.0, 0.5, 0.0), Vec3::X);
+
+        assert_eq!(
+            pick(ray, None, players()),
+            Some(Pick::Player("near".to_string()))
+        );
+
+        let away = Ray3d::new(ray.origin, -Vec3::X);
+        assert_eq!(pick(away, None, players()), None);
+    }
+
+    #[test]
+    fn pick_chooses_between_terrain_and_players() {
+        let tile_map = TileMap::flat(20, 20, 1.0);
+        let player = GlobalTransform::from_translation(Vec3::new(4.0, 0.5, 0.0));
+        let players = || std::iter::once(("friend".to_string(), &player));
+
+        // Looking down at the player from above
+        let ray = ray(Vec3::new(4.0, 5.0, 0.0), -Vec3::Y);
+        assert_eq!(
+            pick(ray, Some(&tile_map), players()),
+            Some(Pick::Player("friend".to_string()))
+        );
+
+        // Looking down next to the player
+        let ray = Ray3d::new(Vec3::new(-4.0, 5.0, 0.0), -Vec3::Y);
+        let Some(Pick::Terrain(point)) = pick(ray, Some(&tile_map), players()) else {
+            panic!("expected to pick the terrain");
+        };
+        assert!(point.distance(Vec3::new(-4.0, 0.0, 0.0)) < 1e-3);
+    }
+}
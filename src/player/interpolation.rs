@@ -0,0 +1,265 @@
+use super::systems::{FriendTag, FriendUpdateEvent};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// This module smooths friend movement with snapshot interpolation.
+/// Every position received for a friend is buffered with its arrival time, and friends are
+/// rendered `delay` in the past, lerping between the two snapshots around that moment.
+/// When no newer snapshot has arrived yet, the friend keeps moving along their last velocity
+/// for up to `max_extrapolation`, then eases back to the last known position.
+
+#[derive(Clone, Debug, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct Interpolation {
+    // How far behind the latest snapshot friends are rendered, a few broadcasts' worth
+    pub delay: Duration,
+    // How long to keep moving a friend past their latest snapshot
+    pub max_extrapolation: Duration,
+    // Most snapshots kept per friend
+    pub buffer_size: usize,
+}
+
+impl Default for Interpolation {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_millis(100),
+            max_extrapolation: Duration::from_millis(100),
+            buffer_size: 32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Snapshot {
+    // Seconds since startup when the position arrived
+    pub received_at: f64,
+    pub position: Vec3,
+}
+
+impl Snapshot {
+    pub fn new(received_at: f64, position: Vec3) -> Self {
+        Self {
+            received_at,
+            position,
+        }
+    }
+}
+
+#[derive(Component, Debug, Default)]
+pub struct SnapshotBuffer {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl SnapshotBuffer {
+    pub fn new(snapshot: Snapshot) -> Self {
+        Self {
+            snapshots: VecDeque::from([snapshot]),
+        }
+    }
+
+    pub fn push(&mut self, snapshot: Snapshot, buffer_size: usize) {
+        match self.snapshots.back_mut() {
+            // Several updates can arrive in the same frame, only the latest one matters
+            Some(latest) if latest.received_at >= snapshot.received_at => {
+                latest.position = snapshot.position;
+            }
+            _ => self.snapshots.push_back(snapshot),
+        }
+
+        while self.snapshots.len() > buffer_size.max(2) {
+            self.snapshots.pop_front();
+        }
+    }
+
+    // Position at render_time, interpolated between snapshots or extrapolated past the latest
+    pub fn sample(&self, render_time: f64, max_extrapolation: f64) -> Option<Vec3> {
+        let first = self.snapshots.front()?;
+        let latest = self.snapshots.back()?;
+
+        if render_time >= latest.received_at {
+            return Some(self.extrapolate(render_time, max_extrapolation));
+        }
+        if render_time <= first.received_at {
+            return Some(first.position);
+        }
+
+        let (from, to) = self
+            .snapshots
+            .iter()
+            .zip(self.snapshots.iter().skip(1))
+            .find(|(_, to)| to.received_at > render_time)?;
+        let span = to.received_at - from.received_at;
+        let t = ((render_time - from.received_at) / span) as f32;
+
+        Some(from.position.lerp(to.position, t))
+    }
+
+    fn extrapolate(&self, render_time: f64, max_extrapolation: f64) -> Vec3 {
+        let latest = self.snapshots[self.snapshots.len() - 1];
+        if self.snapshots.len() < 2 {
+            return latest.position;
+        }
+
+        let previous = self.snapshots[self.snapshots.len() - 2];
+        let span = latest.received_at - previous.received_at;
+        let velocity = (latest.position - previous.position) / span as f32;
+
+        // Move ahead for up to max_extrapolation, then ease back over the same duration,
+        // so a friend who stopped moving doesn't stay overshot
+        let ahead = render_time - latest.received_at;
+        let ahead = if ahead <= max_extrapolation {
+            ahead
+        } else {
+            (2.0 * max_extrapolation - ahead).max(0.0)
+        };
+
+        latest.position + velocity * ahead as f32
+    }
+
+    // Drop snapshots that are no longer needed to sample at render_time
+    pub fn prune(&mut self, render_time: f64) {
+        while self.snapshots.len() > 2 && self.snapshots[1].received_at <= render_time {
+            self.snapshots.pop_front();
+        }
+    }
+}
+
+pub fn record_friend_snapshots(
+    mut update_event_reader: EventReader<FriendUpdateEvent>,
+    mut friend_query: Query<(&FriendTag, &mut SnapshotBuffer)>,
+    interpolation: Res<Interpolation>,
+    time: Res<Time>,
+) {
+    let mut buffers: HashMap<&str, Mut<SnapshotBuffer>> = friend_query
+        .iter_mut()
+        .map(|(friend_tag, buffer)| (friend_tag.player_uuid.as_str(), buffer))
+        .collect();
+
+    let received_at = time.elapsed_seconds_f64();
+    for FriendUpdateEvent {
+        player_uuid,
+        new_position,
+    } in update_event_reader.read()
+    {
+        // Friends spawned this frame start with their first snapshot already
+        if let Some(buffer) = buffers.get_mut(player_uuid.as_str()) {
+            buffer.push(
+                Snapshot::new(received_at, *new_position),
+                interpolation.buffer_size,
+            );
+        }
+    }
+}
+
+pub fn interpolate_friend_positions(
+    mut friend_query: Query<(&mut Transform, &mut SnapshotBuffer), With<FriendTag>>,
+    interpolation: Res<Interpolation>,
+    time: Res<Time>,
+) {
+    let render_time = time.elapsed_seconds_f64() - interpolation.delay.as_secs_f64();
+    let max_extrapolation = interpolation.max_extrapolation.as_secs_f64();
+
+    for (mut transform, mut buffer) in friend_query.iter_mut() {
+        let Some(position) = buffer.sample(render_time, max_extrapolation) else {
+            continue;
+        };
+
+        // Skip if no position change
+        if transform.translation != position {
+            transform.translation = position;
+        }
+        buffer.prune(render_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_EXTRAPOLATION: f64 = 0.1;
+
+    fn snapshot(received_at: f64, x: f32) -> Snapshot {
+        Snapshot::new(received_at, Vec3::new(x, 0.0, 0.0))
+    }
+
+    // A friend moving along x at 10 units per second, one snapshot every 0.1s
+    fn moving_buffer(count: usize) -> SnapshotBuffer {
+        let mut buffer = SnapshotBuffer::new(snapshot(0.0, 0.0));
+        for i in 1..count {
+            buffer.push(snapshot(i as f64 * 0.1, i as f32), 32);
+        }
+        buffer
+    }
+
+    fn sample_x(buffer: &SnapshotBuffer, render_time: f64) -> f32 {
+        buffer.sample(render_time, MAX_EXTRAPOLATION).unwrap().x
+    }
+
+    #[test]
+    fn interpolates_between_bracketing_snapshots() {
+        let buffer = moving_buffer(3);
+
+        assert!((sample_x(&buffer, 0.05) - 0.5).abs() < 1e-4);
+        assert!((sample_x(&buffer, 0.175) - 1.75).abs() < 1e-4);
+        assert_eq!(sample_x(&buffer, 0.1), 1.0);
+        // Before the oldest snapshot the friend waits at their first position
+        assert_eq!(sample_x(&buffer, -1.0), 0.0);
+    }
+
+    #[test]
+    fn extrapolation_is_clamped_at_max_extrapolation() {
+        let buffer = moving_buffer(2);
+
+        // Keeps moving along the last velocity...
+        assert!((sample_x(&buffer, 0.15) - 1.5).abs() < 1e-4);
+        assert!((sample_x(&buffer, 0.2) - 2.0).abs() < 1e-4);
+        // ...then eases back and stays at the last known position
+        assert!((sample_x(&buffer, 0.25) - 1.5).abs() < 1e-4);
+        assert_eq!(sample_x(&buffer, 0.3), 1.0);
+        assert_eq!(sample_x(&buffer, 10.0), 1.0);
+    }
+
+    #[test]
+    fn empty_or_single_snapshot_buffer() {
+        assert_eq!(
+            SnapshotBuffer::default().sample(1.0, MAX_EXTRAPOLATION),
+            None
+        );
+
+        let buffer = moving_buffer(1);
+        assert_eq!(sample_x(&buffer, -1.0), 0.0);
+        assert_eq!(sample_x(&buffer, 0.05), 0.0);
+        assert_eq!(sample_x(&buffer, 1.0), 0.0);
+    }
+
+    #[test]
+    fn snapshot_not_newer_than_the_latest_replaces_it() {
+        let mut buffer = moving_buffer(2);
+
+        buffer.push(snapshot(0.1, 5.0), 32);
+        buffer.push(snapshot(0.05, 7.0), 32);
+
+        assert_eq!(buffer.snapshots.len(), 2);
+        assert_eq!(buffer.snapshots[1].received_at, 0.1);
+        assert_eq!(buffer.snapshots[1].position.x, 7.0);
+        assert!((sample_x(&buffer, 0.05) - 3.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn push_trims_to_buffer_size() {
+        let mut buffer = moving_buffer(1);
+        for i in 1..10 {
+            buffer.push(snapshot(i as f64 * 0.1, i as f32), 4);
+        }
+
+        let kept: Vec<f32> = buffer.snapshots.iter().map(|s| s.position.x).collect();
+        assert_eq!(kept, [6.0, 7.0, 8.0, 9.0]);
+
+        // At least two snapshots are always kept to extrapolate from
+        buffer.push(snapshot(1.0, 10.0), 0);
+        let kept: Vec<f32> = buffer.snapshots.iter().map(|s| s.position.x).collect();
+        assert_eq!(kept, [9.0, 10.0]);
+    }
+}
@@ -1,17 +1,43 @@
+pub mod interpolation;
+pub mod path;
 pub mod player;
+pub mod prediction;
 pub mod store;
 pub mod systems;
 
+use self::interpolation::{interpolate_friend_positions, record_friend_snapshots, Interpolation};
+use self::path::start_path_to_click;
+use self::prediction::{reconcile_player_position, InputHistory};
 use self::{store::PlayerStore, systems::*};
+use crate::picking::{send_click_events, TerrainClicked};
 use crate::schedule::{StartupSet, UpdateSet};
 use bevy::prelude::*;
 
 #[derive(Clone, Debug)]
-pub struct PlayerPlugin {}
+pub struct Config {
+    pub interpolation: Interpolation,
+    pub movement: Movement,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            interpolation: Interpolation::default(),
+            movement: Movement::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlayerPlugin {
+    pub config: Config,
+}
 
 impl Default for PlayerPlugin {
     fn default() -> Self {
-        Self {}
+        Self {
+            config: Config::default(),
+        }
     }
 }
 
@@ -21,17 +47,37 @@ impl Plugin for PlayerPlugin {
             .register_type::<PlayerStore>()
             .insert_resource(BroadcastBuffer::default())
             .register_type::<BroadcastBuffer>()
+            .insert_resource(self.config.movement.clone())
+            .register_type::<Movement>()
+            .insert_resource(InputHistory::default())
+            .insert_resource(self.config.interpolation.clone())
+            .register_type::<Interpolation>()
             .add_systems(Startup, spawn_player.in_set(StartupSet::SpawnEntities))
+            .add_systems(FixedUpdate, update_player_position)
+            .add_systems(
+                Update,
+                broadcast_player_update.in_set(UpdateSet::UserInputEffects),
+            )
             .add_systems(
                 Update,
-                (update_player_position, broadcast_player_update)
-                    .chain()
+                start_path_to_click
+                    .after(send_click_events)
                     .in_set(UpdateSet::UserInputEffects),
             )
             .add_systems(
                 Update,
-                (spawn_friends, despawn_friends, update_friend_positions),
+                reconcile_player_position.in_set(UpdateSet::AfterEffects),
+            )
+            .add_systems(
+                Update,
+                (
+                    spawn_friends,
+                    despawn_friends,
+                    (record_friend_snapshots, interpolate_friend_positions).chain(),
+                ),
             )
+            // Clicks come from PickingPlugin when it is added
+            .add_event::<TerrainClicked>()
             .add_event::<PlayerUpdateEvent>()
             .add_event::<FriendUpdateEvent>();
     }
@@ -0,0 +1,133 @@
+use super::systems::{FriendTag, PlayerTag};
+use crate::picking::TerrainClicked;
+use crate::terrain::pathfinding::find_path;
+use crate::terrain::tile_map::TileMap;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+/// This module contains click-to-move for the local player.
+/// Clicking the terrain finds an A* path from the player's tile to the clicked tile, which
+/// `update_player_position` then follows at top speed, sending player updates along the way
+/// like any other movement. Moving with WASD cancels the path.
+/// Bots follow `MovePath`s too, to wander around obstacles instead of into them.
+
+// Waypoints to walk through in order, only their x and z are followed
+#[derive(Component, Clone, Debug, Default)]
+pub struct MovePath {
+    waypoints: VecDeque<Vec3>,
+}
+
+impl MovePath {
+    // Path from one position to another through the centers of the tiles between them,
+    // ending exactly at `to`. None if `to` can't be reached.
+    pub fn find(tile_map: &TileMap, from: Vec3, to: Vec3) -> Option<Self> {
+        let from_coords = tile_map.tile_coords(from)?;
+        let tiles = find_path(tile_map, from_coords, tile_map.tile_coords(to)?)?;
+        if tiles.is_empty() {
+            return Some(Self {
+                waypoints: VecDeque::from([to]),
+            });
+        }
+
+        // Going through the center of the current tile first keeps every leg between tile
+        // centers, as checked by find_path, and the last tile is replaced by the destination
+        let mut waypoints: VecDeque<Vec3> = std::iter::once(from_coords)
+            .chain(tiles)
+            .map(|coords| tile_map.tile_center(coords))
+            .collect();
+        waypoints.pop_back();
+        waypoints.push_back(to);
+
+        Some(Self { waypoints })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waypoints.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.waypoints.clear();
+    }
+
+    // Position after moving up to max_distance along the path, dropping the waypoints passed.
+    // None if there is nowhere left to go.
+    pub fn advance(&mut self, position: Vec3, max_distance: f32) -> Option<Vec3> {
+        if self.waypoints.is_empty() {
+            return None;
+        }
+
+        let mut position = position;
+        let mut remaining = max_distance;
+        while let Some(&waypoint) = self.waypoints.front() {
+            let to_waypoint = Vec3::new(waypoint.x - position.x, 0.0, waypoint.z - position.z);
+            let distance = to_waypoint.length();
+            if distance > remaining {
+                return Some(position + to_waypoint / distance * remaining);
+            }
+
+            position = Vec3::new(waypoint.x, position.y, waypoint.z);
+            remaining -= distance;
+            self.waypoints.pop_front();
+        }
+
+        Some(position)
+    }
+}
+
+pub fn start_path_to_click(
+    mut event_reader: EventReader<TerrainClicked>,
+    mut player_query: Query<(&Transform, &mut MovePath), (With<PlayerTag>, Without<FriendTag>)>,
+    tile_map: Option<Res<TileMap>>,
+) {
+    // Only the latest click matters
+    let Some(&TerrainClicked(destination)) = event_reader.read().last() else {
+        return;
+    };
+    let Some(tile_map) = tile_map else {
+        return;
+    };
+
+    for (transform, mut path) in player_query.iter_mut() {
+        match MovePath::find(&tile_map, transform.translation, destination) {
+            Some(new_path) => *path = new_path,
+            None => {
+                debug!("no path to {destination}");
+                path.clear();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::tile_map::{Tile, TileKind};
+
+    #[test]
+    fn path_starts_from_the_center_of_the_current_tile() {
+        let mut tile_map = TileMap::flat(2, 2, 1.0);
+        tile_map.set(UVec2::new(0, 1), Tile::new(TileKind::Wall, 0.0));
+        // Off center, towards the wall
+        let from = tile_map.tile_center(UVec2::new(0, 0)) + Vec3::new(-0.3, 0.0, 0.3);
+        let to = tile_map.tile_center(UVec2::new(1, 1));
+
+        let path = MovePath::find(&tile_map, from, to).unwrap();
+        assert_eq!(
+            Vec::from(path.waypoints),
+            vec![
+                tile_map.tile_center(UVec2::new(0, 0)),
+                tile_map.tile_center(UVec2::new(1, 0)),
+                to,
+            ]
+        );
+    }
+
+    #[test]
+    fn path_within_a_tile_goes_straight_to_the_destination() {
+        let tile_map = TileMap::flat(2, 2, 1.0);
+        let center = tile_map.tile_center(UVec2::ZERO);
+
+        let path = MovePath::find(&tile_map, center, center + Vec3::X * 0.2).unwrap();
+        assert_eq!(Vec::from(path.waypoints), vec![center + Vec3::X * 0.2]);
+    }
+}
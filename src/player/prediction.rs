@@ -0,0 +1,215 @@
+use super::systems::{keep_on_terrain, FriendTag, PlayerTag, PlayerUpdateEvent};
+use crate::socket::response::Response;
+use crate::socket::ResponseEvent;
+use crate::terrain::tile_map::TileMap;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+/// This module contains client-side prediction for the local player.
+/// Every movement input is numbered and applied right away, and kept in the `InputHistory`
+/// until the server acknowledges the position it led to. When the server's position differs
+/// from the predicted one, e.g. because it enforced a speed limit, the player is moved to the
+/// server's position and the inputs the server has not seen yet are replayed on top of it.
+
+// Inputs kept while waiting for acknowledgements, about 10s of movement at 30ms broadcasts
+const MAX_INPUT_HISTORY: usize = 300;
+// Predicted positions closer than this to the server's are considered in agreement
+const RECONCILE_TOLERANCE: f32 = 0.001;
+
+#[derive(Clone, Copy, Debug)]
+struct Input {
+    seq: u32,
+    // Movement applied by this input
+    delta: Vec3,
+    // Predicted position after this input
+    position: Vec3,
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct InputHistory {
+    seq: u32,
+    inputs: VecDeque<Input>,
+}
+
+impl InputHistory {
+    // Record an input that has just been applied, returning its sequence id
+    pub fn record(&mut self, delta: Vec3, position: Vec3) -> u32 {
+        // Wraps around after u32::MAX, skipping 0 which older clients send for "no seq"
+        self.seq = self.seq.wrapping_add(1).max(1);
+        self.inputs.push_back(Input {
+            seq: self.seq,
+            delta,
+            position,
+        });
+
+        if self.inputs.len() > MAX_INPUT_HISTORY {
+            self.inputs.pop_front();
+        }
+
+        self.seq
+    }
+
+    pub fn latest_seq(&self) -> u32 {
+        self.seq
+    }
+
+    // Drop the inputs the server has processed up to seq. If the server's position differs
+    // from the one predicted for seq, returns the corrected position with later inputs replayed,
+    // each passed through `constrain(from, to)` like the original movement was.
+    pub fn reconcile(
+        &mut self,
+        seq: u32,
+        server_position: Vec3,
+        constrain: impl Fn(Vec3, Vec3) -> Vec3,
+    ) -> Option<Vec3> {
+        // Acks for inputs we no longer track (or never sent) are stale.
+        // Inputs are kept in the order they were sent, so the ones after the acked input are
+        // still pending even if seq wrapped around in between.
+        let acked = self.inputs.iter().position(|input| input.seq == seq)?;
+        let predicted = self.inputs[acked].position;
+        self.inputs.drain(..=acked);
+
+        if predicted.distance(server_position) <= RECONCILE_TOLERANCE {
+            return None;
+        }
+
+        let mut position = server_position;
+        for input in self.inputs.iter_mut() {
+            position = constrain(position, position + input.delta);
+            input.position = position;
+        }
+
+        Some(position)
+    }
+}
+
+// The server acks each player_update with the position it accepted, only to the player who sent it
+pub fn reconcile_player_position(
+    mut response_event_reader: EventReader<ResponseEvent>,
+    mut history: ResMut<InputHistory>,
+    mut player_query: Query<&mut Transform, (With<PlayerTag>, Without<FriendTag>)>,
+    tile_map: Option<Res<TileMap>>,
+    mut event_writer: EventWriter<PlayerUpdateEvent>,
+) {
+    for ResponseEvent { response } in response_event_reader.read() {
+        let Response::PlayerUpdateAck(ack) = response else {
+            continue;
+        };
+
+        let constrain = |from, to| keep_on_terrain(tile_map.as_deref(), from, to);
+        let Some(position) = history.reconcile(ack.seq, ack.position, constrain) else {
+            continue;
+        };
+        debug!(
+            "reconciled seq={} server={:?} corrected={:?}",
+            ack.seq, ack.position, position
+        );
+
+        for mut transform in player_query.iter_mut() {
+            transform.translation = position;
+        }
+
+        // Re-send so the server gets the corrected position for the latest input
+        event_writer.send(PlayerUpdateEvent::new(position));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Moves along x with no obstacles
+    fn unconstrained(_from: Vec3, to: Vec3) -> Vec3 {
+        to
+    }
+
+    // Records `count` inputs moving one unit along x each, starting from the origin
+    fn walk(history: &mut InputHistory, count: usize) -> Vec<u32> {
+        (1..=count)
+            .map(|i| history.record(Vec3::X, Vec3::new(i as f32, 0.0, 0.0)))
+            .collect()
+    }
+
+    fn pending_seqs(history: &InputHistory) -> Vec<u32> {
+        history.inputs.iter().map(|input| input.seq).collect()
+    }
+
+    #[test]
+    fn ack_mid_history_replays_later_inputs() {
+        let mut history = InputHistory::default();
+        let seqs = walk(&mut history, 5);
+
+        // The server held the player back by half a unit at the second input
+        let corrected = history.reconcile(seqs[1], Vec3::new(1.5, 0.0, 0.0), unconstrained);
+
+        assert_eq!(corrected, Some(Vec3::new(4.5, 0.0, 0.0)));
+        assert_eq!(pending_seqs(&history), &seqs[2..]);
+        let positions: Vec<f32> = history
+            .inputs
+            .iter()
+            .map(|input| input.position.x)
+            .collect();
+        assert_eq!(positions, [2.5, 3.5, 4.5]);
+    }
+
+    #[test]
+    fn replay_goes_through_constrain() {
+        let mut history = InputHistory::default();
+        let seqs = walk(&mut history, 4);
+
+        // A wall at x = 2.5 stops the replayed movement
+        let wall = |_from: Vec3, to: Vec3| to.min(Vec3::new(2.5, 0.0, 0.0));
+        let corrected = history.reconcile(seqs[0], Vec3::new(0.5, 0.0, 0.0), wall);
+
+        assert_eq!(corrected, Some(Vec3::new(2.5, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn ack_within_tolerance_is_not_corrected() {
+        let mut history = InputHistory::default();
+        let seqs = walk(&mut history, 3);
+
+        let server_position = Vec3::new(2.0 + RECONCILE_TOLERANCE / 2.0, 0.0, 0.0);
+        let corrected = history.reconcile(seqs[1], server_position, unconstrained);
+
+        assert_eq!(corrected, None);
+        // The acked inputs are still dropped
+        assert_eq!(pending_seqs(&history), &seqs[2..]);
+        assert_eq!(history.inputs[0].position, Vec3::new(3.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn ack_for_pruned_or_unknown_seq_is_ignored() {
+        let mut history = InputHistory::default();
+        let seqs = walk(&mut history, MAX_INPUT_HISTORY + 10);
+        assert_eq!(history.inputs.len(), MAX_INPUT_HISTORY);
+
+        // Fell out of the history
+        assert_eq!(history.reconcile(seqs[0], Vec3::ZERO, unconstrained), None);
+        // Already acknowledged
+        history.reconcile(seqs[20], Vec3::ZERO, unconstrained);
+        assert_eq!(history.reconcile(seqs[15], Vec3::ZERO, unconstrained), None);
+        // Never sent
+        assert_eq!(history.reconcile(u32::MAX, Vec3::ZERO, unconstrained), None);
+
+        assert_eq!(pending_seqs(&history), &seqs[21..]);
+    }
+
+    #[test]
+    fn seq_wraps_around_and_keeps_ordering() {
+        let mut history = InputHistory {
+            seq: u32::MAX - 2,
+            ..default()
+        };
+        let seqs = walk(&mut history, 5);
+
+        assert_eq!(seqs, [u32::MAX - 1, u32::MAX, 1, 2, 3]);
+        assert_eq!(history.latest_seq(), 3);
+
+        // Acking the input before the wrap keeps the ones after it, even with smaller seqs
+        let corrected = history.reconcile(u32::MAX, Vec3::new(1.0, 0.0, 0.0), unconstrained);
+
+        assert_eq!(corrected, Some(Vec3::new(4.0, 0.0, 0.0)));
+        assert_eq!(pending_seqs(&history), [1, 2, 3]);
+    }
+}
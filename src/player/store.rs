@@ -11,7 +11,12 @@ pub struct PlayerStore {
 
 impl Default for PlayerStore {
     fn default() -> Self {
-        let player = Player::new_with_username_from_env_or_generate();
+        Self::new(Player::new_with_username_from_env_or_generate())
+    }
+}
+
+impl PlayerStore {
+    pub fn new(player: Player) -> Self {
         let mut players = HashMap::new();
         players.insert(player.uuid.clone(), player.clone());
 
@@ -20,9 +25,7 @@ impl Default for PlayerStore {
             players,
         }
     }
-}
 
-impl PlayerStore {
     pub fn get_player(&self) -> &Player {
         self.players.get(&self.player_uuid).unwrap()
     }
@@ -50,6 +53,11 @@ impl PlayerStore {
         }
     }
 
+    pub fn remove_friends(&mut self) {
+        let player_uuid = self.player_uuid.clone();
+        self.players.retain(|uuid, _player| *uuid == player_uuid);
+    }
+
     pub fn upsert_player(&mut self, player: Player) {
         self.players
             .entry(player.uuid.clone())
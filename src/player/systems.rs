@@ -1,23 +1,44 @@
+use super::interpolation::{Snapshot, SnapshotBuffer};
+use super::path::MovePath;
+use super::prediction::InputHistory;
 use super::store::PlayerStore;
 use crate::cameras::SceneCamera;
+use crate::collision::collider::MeshCollider;
 use crate::socket::client::SocketStatus;
 use crate::socket::request::Request;
 use crate::socket::{Socket, GAME_ROOM};
-use crate::terrain::Terrain;
+use crate::terrain::tile_map::TileMap;
 use bevy::prelude::*;
-use rand::Rng;
 use std::time::Duration;
 
 pub const PLAYER_SIZE: f32 = 0.2;
-const BROADCAST_THROTTLE_MS: u64 = 30;
-
-// TODO: This is weird
-const MOVEMENT_X_SPEED: f32 = 0.085;
-const MOVEMENT_Z_SPEED: f32 = 0.125;
+pub const BROADCAST_THROTTLE_MS: u64 = 30;
 
 #[derive(Component, Debug)]
 pub struct PlayerTag;
 
+// Current velocity of the local player in units per second
+#[derive(Component, Debug, Default)]
+pub struct Velocity(pub Vec3);
+
+#[derive(Clone, Debug, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct Movement {
+    // Top speed in units per second
+    pub speed: f32,
+    // How quickly the player reaches top speed, or comes to a stop, in units per second squared
+    pub acceleration: f32,
+}
+
+impl Default for Movement {
+    fn default() -> Self {
+        Self {
+            speed: 2.5,
+            acceleration: 20.0,
+        }
+    }
+}
+
 #[derive(Component, Debug)]
 pub struct FriendTag {
     pub player_uuid: String,
@@ -72,36 +93,22 @@ pub fn spawn_player(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    terrain_query: Query<(&Transform, &Terrain), With<Terrain>>,
+    tile_map: Option<Res<TileMap>>,
     mut event_writer: EventWriter<PlayerUpdateEvent>,
+    mut history: ResMut<InputHistory>,
 ) {
-    let Ok((terrain_transform, terrain)) = terrain_query.get_single() else {
+    let Some(tile_map) = tile_map else {
         return;
     };
 
-    let Terrain {
-        width: terrain_width,
-        height: terrain_height,
-        depth: terrain_depth,
-    } = terrain;
-
-    // Pin player to top of the terrain
-    let terrain_top_y = terrain_transform.translation.y + (terrain_height / 2.0);
-    let player_y = terrain_top_y + (PLAYER_SIZE / 2.0);
-
-    // Randomly place the player within width/depth bounds of the terrain
-    let mut rng = rand::thread_rng();
-    let player_x = rng.gen_range(
-        terrain_transform.translation.x - terrain_width / 2.0
-            ..terrain_transform.translation.x + terrain_width / 2.0,
-    );
-    let player_z = rng.gen_range(
-        terrain_transform.translation.z - terrain_depth / 2.0
-            ..terrain_transform.translation.z + terrain_depth / 2.0,
-    );
-
-    let player_position = Vec3::new(player_x, player_y, player_z);
+    // Randomly place the player on a walkable tile, resting on top of it
+    let Some(player_position) = tile_map.random_walkable_position(PLAYER_SIZE / 2.0) else {
+        error!("no walkable tiles to spawn the player on");
+        return;
+    };
 
+    // Spawning counts as the first input, so the server can correct the spawn point too
+    history.record(Vec3::ZERO, player_position);
     event_writer.send(PlayerUpdateEvent::new(player_position));
 
     // Player cube
@@ -117,59 +124,117 @@ pub fn spawn_player(
             ..default()
         },
         PlayerTag,
+        MeshCollider,
+        Velocity::default(),
+        MovePath::default(),
         Name::new("Player"),
     ));
 }
 
+// Runs in FixedUpdate, so players move at the same speed regardless of frame rate
 pub fn update_player_position(
     mut player_query: Query<
-        &mut Transform,
+        (&mut Transform, &mut Velocity, &mut MovePath),
         (With<PlayerTag>, Without<FriendTag>, Without<SceneCamera>),
     >,
     camera_query: Query<&Transform, (With<SceneCamera>, Without<PlayerTag>)>,
+    tile_map: Option<Res<TileMap>>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
+    movement: Res<Movement>,
+    time: Res<Time>,
     mut event_writer: EventWriter<PlayerUpdateEvent>,
+    mut history: ResMut<InputHistory>,
 ) {
     let Ok(camera_transform) = camera_query.get_single() else {
         return;
     };
 
-    let forward = Vec3::from(camera_transform.forward());
-    let right = Vec3::from(camera_transform.right());
+    // Move along the ground relative to the camera, ignoring its tilt
+    let forward = flatten(camera_transform.forward().into());
+    let right = flatten(camera_transform.right().into());
 
-    // TODO: there should really just be one player
-    for mut player_position in player_query.iter_mut() {
-        let mut direction = Vec3::ZERO;
-        let mut did_transform = false;
+    let mut direction = Vec3::ZERO;
+    if keyboard_input.pressed(KeyCode::KeyW) {
+        direction += forward;
+    }
+    if keyboard_input.pressed(KeyCode::KeyS) {
+        direction -= forward;
+    }
+    if keyboard_input.pressed(KeyCode::KeyA) {
+        direction -= right;
+    }
+    if keyboard_input.pressed(KeyCode::KeyD) {
+        direction += right;
+    }
 
-        if keyboard_input.pressed(KeyCode::KeyW) {
-            did_transform = true;
-            direction += forward * MOVEMENT_Z_SPEED;
-        }
-        if keyboard_input.pressed(KeyCode::KeyS) {
-            did_transform = true;
-            direction -= forward * MOVEMENT_Z_SPEED;
-        }
-        if keyboard_input.pressed(KeyCode::KeyA) {
-            did_transform = true;
-            direction -= right * MOVEMENT_X_SPEED;
-        }
-        if keyboard_input.pressed(KeyCode::KeyD) {
-            did_transform = true;
-            direction += right * MOVEMENT_X_SPEED;
-        }
+    // Normalized so moving diagonally is no faster than moving straight
+    let target_velocity = direction.normalize_or_zero() * movement.speed;
+    let delta_seconds = time.delta_seconds();
 
+    // TODO: there should really just be one player
+    for (mut transform, mut velocity, mut path) in player_query.iter_mut() {
+        // Any WASD input takes over from click-to-move
         if direction != Vec3::ZERO {
-            player_position.translation = Vec3::new(
-                player_position.translation.x + direction.x,
-                player_position.translation.y,
-                player_position.translation.z + direction.z,
-            );
+            path.clear();
         }
 
-        if did_transform {
-            event_writer.send(PlayerUpdateEvent::new(player_position.translation));
+        let following_path = !path.is_empty();
+        let target = match path.advance(transform.translation, movement.speed * delta_seconds) {
+            // Paths are followed at top speed
+            Some(target) => target,
+            None => {
+                velocity.0 = move_towards(
+                    velocity.0,
+                    target_velocity,
+                    movement.acceleration * delta_seconds,
+                );
+                if velocity.0 == Vec3::ZERO {
+                    continue;
+                }
+                transform.translation + velocity.0 * delta_seconds
+            }
+        };
+
+        // Slide along walls, water and ledges, dropping any velocity into them
+        let position = keep_on_terrain(tile_map.as_deref(), transform.translation, target);
+        let delta = position - transform.translation;
+        // Stop dead at the end of a path rather than drifting past it
+        velocity.0 = if following_path && path.is_empty() {
+            Vec3::ZERO
+        } else {
+            delta / delta_seconds
+        };
+        if delta == Vec3::ZERO {
+            // Give up on a path that has become blocked, e.g. by an edited tile
+            path.clear();
+            continue;
         }
+        transform.translation = position;
+
+        history.record(delta, transform.translation);
+        event_writer.send(PlayerUpdateEvent::new(transform.translation));
+    }
+}
+
+// Move a player towards a position, staying on walkable tiles and resting on top of them
+pub fn keep_on_terrain(tile_map: Option<&TileMap>, from: Vec3, to: Vec3) -> Vec3 {
+    match tile_map {
+        Some(tile_map) => tile_map.constrain_move(from, to, PLAYER_SIZE / 2.0),
+        None => to,
+    }
+}
+
+fn flatten(direction: Vec3) -> Vec3 {
+    Vec3::new(direction.x, 0.0, direction.z).normalize_or_zero()
+}
+
+// Step current towards target by at most max_step
+fn move_towards(current: Vec3, target: Vec3, max_step: f32) -> Vec3 {
+    let difference = target - current;
+    if difference.length() <= max_step {
+        target
+    } else {
+        current + difference.normalize() * max_step
     }
 }
 
@@ -178,6 +243,7 @@ pub fn broadcast_player_update(
     mut store: ResMut<PlayerStore>,
     mut broadcast_buffer: ResMut<BroadcastBuffer>,
     socket: Res<Socket>,
+    history: Res<InputHistory>,
     time: Res<Time>,
 ) {
     broadcast_buffer.timer.tick(time.delta());
@@ -199,6 +265,7 @@ pub fn broadcast_player_update(
                         GAME_ROOM.into(),
                         player_uuid.clone(),
                         new_position,
+                        history.latest_seq(),
                     );
                     socket
                         .handle
@@ -217,6 +284,7 @@ pub fn spawn_friends(
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut update_event_reader: EventReader<FriendUpdateEvent>,
     mut store: ResMut<PlayerStore>,
+    time: Res<Time>,
 ) {
     for FriendUpdateEvent {
         player_uuid,
@@ -248,38 +316,16 @@ pub fn spawn_friends(
                 ..default()
             },
             PlayerTag,
+            MeshCollider,
             FriendTag {
                 player_uuid: player_uuid.clone(),
             },
+            SnapshotBuffer::new(Snapshot::new(time.elapsed_seconds_f64(), *new_position)),
             Name::new("Friend"),
         ));
     }
 }
 
-pub fn update_friend_positions(
-    mut friend_query: Query<(&mut Transform, &FriendTag), With<FriendTag>>,
-    store: Res<PlayerStore>,
-) {
-    for (mut current_position, friend_tag) in friend_query.iter_mut() {
-        // Skip unless player can be found in friends
-        let Some(player) = store.get_friend(&friend_tag.player_uuid) else {
-            continue;
-        };
-
-        // Skip unless friend position exists
-        let Some(new_position) = player.position else {
-            continue;
-        };
-
-        // Skip if no position change
-        if current_position.translation == new_position {
-            continue;
-        }
-
-        current_position.translation = new_position;
-    }
-}
-
 // Despawn friend entities that cannot be found in socket.friends
 pub fn despawn_friends(
     mut commands: Commands,
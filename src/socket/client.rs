@@ -1,36 +1,90 @@
-use super::{refs::Refs, response::Response};
+use super::message::{Message, Payload};
+use super::metrics::SocketMetrics;
+use super::overflow::OverflowBuffer;
+use super::refs::{RefAllocator, Refs};
+use super::reply::{PendingRequests, Reply, EXPIRE_INTERVAL_MS};
+use super::response::Response;
+use super::Config;
 use crate::socket::request::Request;
 use async_trait::async_trait;
 use bevy::log::prelude::*;
 use ezsockets::{client::ClientCloseMode, CloseFrame, Error as SocketError, WSError};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 use tokio::sync::mpsc;
 
 /// This module contains the `Client` struct and ezsockets client implementation.
 /// It handles internal calls and relays messages to the server.
+/// A client stops once its connection is lost, and the `Socket` schedules a new one.
 
 #[derive(Debug)]
 pub struct Client {
     pub handle: ezsockets::Client<Self>,
     pub tx: mpsc::Sender<SocketEvent>,
-    refs: Refs,
+    refs: RefAllocator,
+    pending: PendingRequests,
+    config: Config,
+    overflow: OverflowBuffer,
+    metrics: SocketMetrics,
 }
 
 impl Client {
-    pub fn new(handle: ezsockets::Client<Self>, tx: mpsc::Sender<SocketEvent>) -> Self {
+    pub fn new(
+        handle: ezsockets::Client<Self>,
+        tx: mpsc::Sender<SocketEvent>,
+        config: Config,
+        pending: PendingRequests,
+        overflow: OverflowBuffer,
+        metrics: SocketMetrics,
+    ) -> Self {
         Self {
             handle,
             tx,
-            refs: Refs::default(),
+            refs: RefAllocator::default(),
+            pending,
+            config,
+            overflow,
+            metrics,
         }
     }
 
-    pub fn next_refs(&self) -> Refs {
-        let new_message_ref = self.refs.message_ref.fetch_add(1, Ordering::SeqCst);
+    pub fn next_refs(&self, request: &Request) -> Refs {
+        self.refs.next_refs(request)
+    }
+
+    async fn send_event(&self, event: SocketEvent) {
+        if let Err(e) = self.tx.send(event).await {
+            error!("error sending message to channel: {e}");
+        }
+    }
+
+    // Relay a decoded message from the server to the channel
+    async fn relay_message(&mut self, message: Message) {
+        // Resolve the request this message is a reply to, if any
+        if let Some(reply) = self.pending.resolve(&message) {
+            self.send_event(SocketEvent::Reply(reply)).await;
+        }
 
-        Refs {
-            join_ref: self.refs.join_ref.clone(),
-            message_ref: AtomicUsize::new(new_message_ref + 1),
+        // Skip messages whose payload we cannot decode. Player updates never wait for room in the
+        // channel, so a busy frame can't stall the socket.
+        match Response::new_from_message(message) {
+            Ok(Response::PlayerUpdate(player_update)) => {
+                self.overflow.send(&self.tx, player_update, &self.metrics)
+            }
+            Ok(response) => self.send_event(SocketEvent::Response(response)).await,
+            Err(e) => warn!("skipping frame: {e}"),
+        }
+    }
+}
+
+// Relay replies for requests that have waited too long without one, until the channel closes
+pub async fn expire_pending(pending: PendingRequests, tx: mpsc::Sender<SocketEvent>) {
+    let mut interval = tokio::time::interval(Duration::from_millis(EXPIRE_INTERVAL_MS));
+    loop {
+        interval.tick().await;
+        for reply in pending.expire() {
+            if tx.send(SocketEvent::Reply(reply)).await.is_err() {
+                return;
+            }
         }
     }
 }
@@ -41,6 +95,9 @@ pub enum SocketEvent {
     Connect,
     ConnectFail,
     Disconnect,
+    // Sent by the timer of a scheduled reconnect once its delay is over
+    Reconnect,
+    Reply(Reply),
     Response(Response),
 }
 
@@ -51,6 +108,8 @@ pub enum SocketStatus {
     Connected,
     ConnectFailed,
     Disconnected,
+    // Connected, but the server stopped answering heartbeats
+    Stale,
 }
 
 #[async_trait]
@@ -58,29 +117,54 @@ impl ezsockets::ClientExt for Client {
     type Call = Request;
 
     async fn on_text(&mut self, text: String) -> Result<(), SocketError> {
-        // Relay message from server to channel
-        let response = SocketEvent::Response(Response::new_from_json_string(&text));
-        if let Err(e) = self.tx.send(response).await {
-            error!("error sending message to channel: {e}");
+        match Message::new_from_json_string(&text) {
+            Ok(message) => self.relay_message(message).await,
+            Err(e) => warn!("skipping malformed frame: {e}"),
         }
 
         Ok(())
     }
 
     async fn on_binary(&mut self, bytes: Vec<u8>) -> Result<(), SocketError> {
-        debug!("on_binary={bytes:?}");
+        match Message::new_from_binary(&bytes) {
+            Ok(message) => self.relay_message(message).await,
+            Err(e) => warn!("skipping malformed binary frame: {e}"),
+        }
+
         Ok(())
     }
 
     async fn on_call(&mut self, request: Request) -> Result<(), SocketError> {
         debug!("on_call={:?}", request);
 
-        let request_payload = request.to_payload(self.next_refs());
-        debug!("sending request: {request_payload}");
+        let refs = self.next_refs(&request);
+        if request.expects_reply() {
+            self.pending.track(refs.get_message_ref(), request.clone());
+        }
 
-        self.handle
-            .text(request_payload)
-            .expect("error sending request");
+        let message = request.to_message(refs, self.config.encoding);
+        match message.payload {
+            Payload::Binary(_) => {
+                let request_payload = message
+                    .serialize_to_binary()
+                    .expect("Problem serializing message");
+                debug!("sending binary request: {request_payload:?}");
+
+                self.handle
+                    .binary(request_payload)
+                    .expect("error sending request");
+            }
+            Payload::Json(_) => {
+                let request_payload = message
+                    .serialize_to_json_string()
+                    .expect("Problem serializing message");
+                debug!("sending request: {request_payload}");
+
+                self.handle
+                    .text(request_payload)
+                    .expect("error sending request");
+            }
+        }
 
         Ok(())
     }
@@ -88,9 +172,11 @@ impl ezsockets::ClientExt for Client {
     async fn on_connect(&mut self) -> Result<(), SocketError> {
         debug!("on_connect");
 
-        if let Err(e) = self.tx.send(SocketEvent::Connect).await {
-            error!("error sending message to channel: {e}");
-        }
+        // A new connection starts without any joined channels, or replies still to come
+        self.refs.clear_join_refs();
+        self.pending.clear();
+
+        self.send_event(SocketEvent::Connect).await;
 
         Ok(())
     }
@@ -98,11 +184,9 @@ impl ezsockets::ClientExt for Client {
     async fn on_connect_fail(&mut self, _error: WSError) -> Result<ClientCloseMode, SocketError> {
         error!("on_connect_fail");
 
-        if let Err(e) = self.tx.send(SocketEvent::ConnectFail).await {
-            error!("error sending message to channel: {e}");
-        }
+        self.send_event(SocketEvent::ConnectFail).await;
 
-        Ok(ClientCloseMode::Reconnect)
+        Ok(ClientCloseMode::Close)
     }
 
     async fn on_close(
@@ -111,20 +195,16 @@ impl ezsockets::ClientExt for Client {
     ) -> Result<ClientCloseMode, SocketError> {
         error!("on_close");
 
-        if let Err(e) = self.tx.send(SocketEvent::Close).await {
-            error!("error sending message to channel: {e}");
-        }
+        self.send_event(SocketEvent::Close).await;
 
-        Ok(ClientCloseMode::Reconnect)
+        Ok(ClientCloseMode::Close)
     }
 
     async fn on_disconnect(&mut self) -> Result<ClientCloseMode, SocketError> {
         error!("on_disconnect");
 
-        if let Err(e) = self.tx.send(SocketEvent::Disconnect).await {
-            error!("error sending message to channel: {e}");
-        }
+        self.send_event(SocketEvent::Disconnect).await;
 
-        Ok(ClientCloseMode::Reconnect)
+        Ok(ClientCloseMode::Close)
     }
 }
@@ -1,30 +1,52 @@
 use super::client::SocketEvent;
+use super::metrics::SocketMetrics;
+use super::overflow::OverflowBuffer;
+use super::reply::PendingRequests;
+use super::Config;
 use crate::socket::client::Client;
 use bevy::log::prelude::*;
-use ezsockets::ClientConfig;
+use ezsockets::{ClientConfig, ClientConnectorTokio};
 use std::env;
-use std::future::Future;
+use tokio::runtime::Handle;
 use tokio::sync::mpsc;
 use url::Url;
 
 const DEFAULT_URL: &str = "wss://chat.haunted.host";
 const DEV_URL: &str = "ws://localhost:4000";
 
-pub fn create_channel() -> (mpsc::Sender<SocketEvent>, mpsc::Receiver<SocketEvent>) {
-    mpsc::channel::<SocketEvent>(32)
+pub fn create_channel(capacity: usize) -> (mpsc::Sender<SocketEvent>, mpsc::Receiver<SocketEvent>) {
+    mpsc::channel::<SocketEvent>(capacity)
 }
 
-pub async fn connect_socket(
+// Start a client on the given runtime, relaying its events to tx.
+// Connecting happens in the background, so this returns right away.
+pub fn connect_socket(
+    runtime: &Handle,
     tx: mpsc::Sender<SocketEvent>,
-) -> (
-    ezsockets::Client<Client>,
-    impl Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>>,
-) {
-    let socket_url = get_socket_url();
+    config: Config,
+    pending: PendingRequests,
+    overflow: OverflowBuffer,
+    metrics: SocketMetrics,
+) -> ezsockets::Client<Client> {
+    let socket_url = get_socket_url(&config);
     info!("connecting to {} ...", socket_url);
 
-    let config = ClientConfig::new(socket_url.clone());
-    ezsockets::connect(|handle| Client::new(handle, tx), config).await
+    let client_config = ClientConfig::new(socket_url.clone());
+    let (handle, mut future) = ezsockets::connect_with(
+        |handle| Client::new(handle, tx, config, pending, overflow, metrics),
+        client_config,
+        ClientConnectorTokio::new(runtime.clone()),
+    );
+
+    runtime.spawn(async move {
+        match future.extract().await {
+            Ok(Ok(())) => debug!("socket client stopped"),
+            Ok(Err(e)) => error!("socket client stopped: {e}"),
+            Err(e) => error!("socket client crashed: {e:?}"),
+        }
+    });
+
+    handle
 }
 
 #[allow(dead_code)]
@@ -46,8 +68,10 @@ pub fn close_socket(handle: ezsockets::Client<Client>) -> std::io::Result<()> {
     }
 }
 
-pub fn get_socket_url() -> Url {
-    let base_url = if env::var("DEV").unwrap_or_default() == "true" {
+pub fn get_socket_url(config: &Config) -> Url {
+    let base_url = if let Some(url) = &config.url {
+        url.clone()
+    } else if env::var("DEV").unwrap_or_default() == "true" {
         DEV_URL.to_string()
     } else if let Ok(custom_url) = env::var("URL") {
         custom_url
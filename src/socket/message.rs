@@ -1,8 +1,9 @@
-use serde_json::{Result as SerdeResult, Value as SerdeValue};
+use serde::de::Error as _;
+use serde_json::{json, Error as SerdeError, Result as SerdeResult, Value as SerdeValue};
 
 /// This module contains the `Message` struct with implementation logic for:
-///   - Parsing JSON from the server into the `Message` struct
-///   - Serializing the `Message` struct into a JSON payload
+///   - Parsing JSON or binary frames from the server into the `Message` struct
+///   - Serializing the `Message` struct into a JSON or binary payload
 
 // The server sends and receives messages as a 5-element JSON array:
 type MessageArray = (
@@ -16,13 +17,50 @@ type MessageArray = (
 type ResponseMessage = MessageArray;
 type RequestMessage = MessageArray;
 
-#[derive(Default, Debug)]
+// Binary frames follow the Phoenix V2 serializer layout:
+//   push:      [kind][join_ref_size][ref_size][topic_size][event_size][join_ref][ref][topic][event][payload]
+//   reply:     [kind][join_ref_size][ref_size][topic_size][status_size][join_ref][ref][topic][status][payload]
+//   broadcast: [kind][topic_size][event_size][topic][event][payload]
+const KIND_PUSH: u8 = 0;
+const KIND_REPLY: u8 = 1;
+const KIND_BROADCAST: u8 = 2;
+
+// Which frame encoding to use for messages that support a binary payload
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Encoding {
+    #[default]
+    Json,
+    Binary,
+}
+
+#[derive(Clone, Debug)]
+pub enum Payload {
+    Json(SerdeValue),
+    Binary(Vec<u8>),
+}
+
+impl Default for Payload {
+    fn default() -> Self {
+        Self::Json(SerdeValue::Null)
+    }
+}
+
+impl Payload {
+    pub fn as_json(&self) -> Option<&SerdeValue> {
+        match self {
+            Self::Json(value) => Some(value),
+            Self::Binary(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug)]
 pub struct Message {
     pub join_ref: Option<String>,
     pub message_ref: Option<usize>,
     pub topic: String,
     pub event: String,
-    pub payload: SerdeValue,
+    pub payload: Payload,
 }
 
 impl Message {
@@ -34,7 +72,7 @@ impl Message {
             message_ref: message_array.1,
             topic: message_array.2,
             event: message_array.3,
-            payload: message_array.4,
+            payload: Payload::Json(message_array.4),
         };
 
         Ok(message)
@@ -42,14 +80,342 @@ impl Message {
 
     // Serialize Message struct into JSON payload
     pub fn serialize_to_json_string(&self) -> SerdeResult<String> {
+        let Payload::Json(payload) = &self.payload else {
+            return Err(SerdeError::custom("binary payload cannot be sent as JSON"));
+        };
+
         let message_array: RequestMessage = (
             self.join_ref.clone(),
             self.message_ref,
             self.topic.clone(),
             self.event.clone(),
-            self.payload.clone(),
+            payload.clone(),
         );
         let json = serde_json::to_string(&message_array)?;
         Ok(json)
     }
+
+    // Parse a binary server frame into Message struct.
+    // Replies are decoded like their JSON counterpart, with the response parsed as JSON if possible.
+    pub fn new_from_binary(bytes: &[u8]) -> SerdeResult<Self> {
+        let mut reader = BinaryReader::new(bytes);
+
+        match reader.read_u8()? {
+            KIND_PUSH => {
+                let join_ref_size = reader.read_u8()?;
+                let topic_size = reader.read_u8()?;
+                let event_size = reader.read_u8()?;
+
+                Ok(Self {
+                    join_ref: non_empty(reader.read_string(join_ref_size)?),
+                    message_ref: None,
+                    topic: reader.read_string(topic_size)?,
+                    event: reader.read_string(event_size)?,
+                    payload: Payload::Binary(reader.read_rest()),
+                })
+            }
+            KIND_REPLY => {
+                let join_ref_size = reader.read_u8()?;
+                let ref_size = reader.read_u8()?;
+                let topic_size = reader.read_u8()?;
+                let status_size = reader.read_u8()?;
+
+                let join_ref = non_empty(reader.read_string(join_ref_size)?);
+                let message_ref = reader
+                    .read_string(ref_size)?
+                    .parse::<usize>()
+                    .map_err(SerdeError::custom)?;
+                let topic = reader.read_string(topic_size)?;
+                let status = reader.read_string(status_size)?;
+                let response: SerdeValue =
+                    serde_json::from_slice(&reader.read_rest()).unwrap_or(SerdeValue::Null);
+
+                Ok(Self {
+                    join_ref,
+                    message_ref: Some(message_ref),
+                    topic,
+                    event: "phx_reply".to_string(),
+                    payload: Payload::Json(json!({ "status": status, "response": response })),
+                })
+            }
+            KIND_BROADCAST => {
+                let topic_size = reader.read_u8()?;
+                let event_size = reader.read_u8()?;
+
+                Ok(Self {
+                    join_ref: None,
+                    message_ref: None,
+                    topic: reader.read_string(topic_size)?,
+                    event: reader.read_string(event_size)?,
+                    payload: Payload::Binary(reader.read_rest()),
+                })
+            }
+            kind => Err(SerdeError::custom(format!(
+                "unknown binary frame kind {kind}"
+            ))),
+        }
+    }
+
+    // Serialize Message struct into a binary push frame
+    pub fn serialize_to_binary(&self) -> SerdeResult<Vec<u8>> {
+        let Payload::Binary(payload) = &self.payload else {
+            return Err(SerdeError::custom("JSON payload cannot be sent as binary"));
+        };
+
+        let join_ref = self.join_ref.clone().unwrap_or_default();
+        let message_ref = self
+            .message_ref
+            .map(|message_ref| message_ref.to_string())
+            .unwrap_or_default();
+
+        let mut bytes = vec![
+            KIND_PUSH,
+            field_size(&join_ref)?,
+            field_size(&message_ref)?,
+            field_size(&self.topic)?,
+            field_size(&self.event)?,
+        ];
+        bytes.extend_from_slice(join_ref.as_bytes());
+        bytes.extend_from_slice(message_ref.as_bytes());
+        bytes.extend_from_slice(self.topic.as_bytes());
+        bytes.extend_from_slice(self.event.as_bytes());
+        bytes.extend_from_slice(payload);
+
+        Ok(bytes)
+    }
+
+    // Parse a binary push frame sent by a client into Message struct.
+    // Unlike pushes from the server, client pushes carry a message_ref.
+    pub fn new_from_binary_request(bytes: &[u8]) -> SerdeResult<Self> {
+        let mut reader = BinaryReader::new(bytes);
+
+        let kind = reader.read_u8()?;
+        if kind != KIND_PUSH {
+            return Err(SerdeError::custom(format!(
+                "expected a binary push frame, got kind {kind}"
+            )));
+        }
+
+        let join_ref_size = reader.read_u8()?;
+        let ref_size = reader.read_u8()?;
+        let topic_size = reader.read_u8()?;
+        let event_size = reader.read_u8()?;
+
+        let join_ref = non_empty(reader.read_string(join_ref_size)?);
+        let message_ref = non_empty(reader.read_string(ref_size)?)
+            .map(|message_ref| message_ref.parse::<usize>())
+            .transpose()
+            .map_err(SerdeError::custom)?;
+
+        Ok(Self {
+            join_ref,
+            message_ref,
+            topic: reader.read_string(topic_size)?,
+            event: reader.read_string(event_size)?,
+            payload: Payload::Binary(reader.read_rest()),
+        })
+    }
+
+    // Serialize Message struct into a binary broadcast frame, as sent by the server
+    pub fn serialize_to_binary_broadcast(&self) -> SerdeResult<Vec<u8>> {
+        let Payload::Binary(payload) = &self.payload else {
+            return Err(SerdeError::custom("JSON payload cannot be sent as binary"));
+        };
+
+        let mut bytes = vec![
+            KIND_BROADCAST,
+            field_size(&self.topic)?,
+            field_size(&self.event)?,
+        ];
+        bytes.extend_from_slice(self.topic.as_bytes());
+        bytes.extend_from_slice(self.event.as_bytes());
+        bytes.extend_from_slice(payload);
+
+        Ok(bytes)
+    }
+}
+
+struct BinaryReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> BinaryReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    fn read_u8(&mut self) -> SerdeResult<u8> {
+        let byte = *self
+            .bytes
+            .get(self.offset)
+            .ok_or_else(|| SerdeError::custom("binary frame ended early"))?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn read_string(&mut self, size: u8) -> SerdeResult<String> {
+        let end = self.offset + size as usize;
+        let bytes = self
+            .bytes
+            .get(self.offset..end)
+            .ok_or_else(|| SerdeError::custom("binary frame ended early"))?;
+        self.offset = end;
+        String::from_utf8(bytes.to_vec()).map_err(SerdeError::custom)
+    }
+
+    fn read_rest(&mut self) -> Vec<u8> {
+        let rest = self.bytes[self.offset..].to_vec();
+        self.offset = self.bytes.len();
+        rest
+    }
+}
+
+// Binary frame fields are prefixed with their size as a single byte
+fn field_size(field: &str) -> SerdeResult<u8> {
+    u8::try_from(field.len())
+        .map_err(|_| SerdeError::custom(format!("binary frame field too long: {field}")))
+}
+
+fn non_empty(value: String) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_message() -> Message {
+        Message {
+            join_ref: Some("1".to_string()),
+            message_ref: Some(42),
+            topic: "game:iso".to_string(),
+            event: "player_update".to_string(),
+            payload: Payload::Binary(vec![1, 2, 3]),
+        }
+    }
+
+    // Reply to ref 42 on game:iso, as the server would send it
+    fn reply_frame() -> Vec<u8> {
+        let mut bytes = vec![KIND_REPLY, 1, 2, 8, 2];
+        bytes.extend_from_slice(b"1");
+        bytes.extend_from_slice(b"42");
+        bytes.extend_from_slice(b"game:iso");
+        bytes.extend_from_slice(b"ok");
+        bytes.extend_from_slice(br#"{"seq":3}"#);
+        bytes
+    }
+
+    fn binary_payload(message: &Message) -> &[u8] {
+        match &message.payload {
+            Payload::Binary(bytes) => bytes,
+            Payload::Json(value) => panic!("expected a binary payload, got {value}"),
+        }
+    }
+
+    #[test]
+    fn push_frame_round_trips() {
+        let message = push_message();
+        let bytes = message.serialize_to_binary().unwrap();
+        let decoded = Message::new_from_binary_request(&bytes).unwrap();
+
+        assert_eq!(decoded.join_ref, message.join_ref);
+        assert_eq!(decoded.message_ref, message.message_ref);
+        assert_eq!(decoded.topic, message.topic);
+        assert_eq!(decoded.event, message.event);
+        assert_eq!(binary_payload(&decoded), binary_payload(&message));
+    }
+
+    #[test]
+    fn push_frame_without_refs_round_trips() {
+        let message = Message {
+            join_ref: None,
+            message_ref: None,
+            ..push_message()
+        };
+        let bytes = message.serialize_to_binary().unwrap();
+        let decoded = Message::new_from_binary_request(&bytes).unwrap();
+
+        assert_eq!(decoded.join_ref, None);
+        assert_eq!(decoded.message_ref, None);
+    }
+
+    #[test]
+    fn broadcast_frame_round_trips() {
+        let message = Message {
+            join_ref: None,
+            message_ref: None,
+            ..push_message()
+        };
+        let bytes = message.serialize_to_binary_broadcast().unwrap();
+        let decoded = Message::new_from_binary(&bytes).unwrap();
+
+        assert_eq!(decoded.join_ref, None);
+        assert_eq!(decoded.message_ref, None);
+        assert_eq!(decoded.topic, message.topic);
+        assert_eq!(decoded.event, message.event);
+        assert_eq!(binary_payload(&decoded), binary_payload(&message));
+    }
+
+    #[test]
+    fn reply_frame_decodes_like_a_json_reply() {
+        let decoded = Message::new_from_binary(&reply_frame()).unwrap();
+
+        assert_eq!(decoded.join_ref.as_deref(), Some("1"));
+        assert_eq!(decoded.message_ref, Some(42));
+        assert_eq!(decoded.topic, "game:iso");
+        assert_eq!(decoded.event, "phx_reply");
+        assert_eq!(
+            decoded.payload.as_json(),
+            Some(&json!({ "status": "ok", "response": { "seq": 3 } }))
+        );
+    }
+
+    #[test]
+    fn json_frame_round_trips() {
+        let message = Message {
+            payload: Payload::Json(json!({ "position": [1.0, 2.0, 3.0] })),
+            ..push_message()
+        };
+        let json = message.serialize_to_json_string().unwrap();
+        let decoded = Message::new_from_json_string(&json).unwrap();
+
+        assert_eq!(decoded.join_ref, message.join_ref);
+        assert_eq!(decoded.message_ref, message.message_ref);
+        assert_eq!(decoded.topic, message.topic);
+        assert_eq!(decoded.event, message.event);
+        assert_eq!(decoded.payload.as_json(), message.payload.as_json());
+    }
+
+    #[test]
+    fn truncated_frames_are_rejected() {
+        let message = push_message();
+        let push = message.serialize_to_binary().unwrap();
+        let broadcast = message.serialize_to_binary_broadcast().unwrap();
+        let payload_size = binary_payload(&message).len();
+
+        // Cutting into the header or fields fails, only the payload's end can't be told apart
+        for size in 0..push.len() - payload_size {
+            assert!(Message::new_from_binary_request(&push[..size]).is_err());
+        }
+        for size in 0..broadcast.len() - payload_size {
+            assert!(Message::new_from_binary(&broadcast[..size]).is_err());
+        }
+
+        let reply = reply_frame();
+        let response_size = br#"{"seq":3}"#.len();
+        for size in 0..reply.len() - response_size {
+            assert!(Message::new_from_binary(&reply[..size]).is_err());
+        }
+    }
+
+    #[test]
+    fn unknown_frame_kind_is_rejected() {
+        assert!(Message::new_from_binary(&[7, 0, 0]).is_err());
+        assert!(Message::new_from_binary_request(&[KIND_BROADCAST, 0, 0]).is_err());
+    }
 }
@@ -0,0 +1,41 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// This module contains `SocketMetrics`, counters shared between the socket client and the game.
+/// They show how often incoming events arrive faster than the game can process them.
+
+#[derive(Clone, Debug, Default)]
+pub struct SocketMetrics {
+    // Player updates that found the channel full
+    overflowed: Arc<AtomicUsize>,
+    // Player updates dropped because a newer one for the same player overflowed or was applied
+    coalesced: Arc<AtomicUsize>,
+    // Frames that used up the event budget with events still queued
+    deferred_frames: Arc<AtomicUsize>,
+}
+
+impl SocketMetrics {
+    pub fn record_overflow(&self) {
+        self.overflowed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_coalesced(&self) {
+        self.coalesced.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_deferred_frame(&self) {
+        self.deferred_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn overflowed(&self) -> usize {
+        self.overflowed.load(Ordering::Relaxed)
+    }
+
+    pub fn coalesced(&self) -> usize {
+        self.coalesced.load(Ordering::Relaxed)
+    }
+
+    pub fn deferred_frames(&self) -> usize {
+        self.deferred_frames.load(Ordering::Relaxed)
+    }
+}
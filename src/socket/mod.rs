@@ -1,24 +1,42 @@
 pub mod client;
 pub mod connection;
 pub mod message;
+pub mod metrics;
+pub mod overflow;
+pub mod reconnect;
 pub mod refs;
+pub mod reply;
 pub mod request;
 pub mod response;
 pub mod room;
 
-use self::client::{Client, SocketEvent, SocketStatus};
+use self::client::{expire_pending, Client, SocketEvent, SocketStatus};
+use self::message::Encoding;
+use self::metrics::SocketMetrics;
+use self::overflow::OverflowBuffer;
+use self::reconnect::ReconnectPolicy;
+use self::reply::{PendingRequests, Reply, ReplyError};
 use self::request::Request;
 use self::response::Response;
+use crate::player::player::Player;
 use crate::player::store::PlayerStore;
-use crate::player::systems::FriendUpdateEvent;
+use crate::player::systems::{FriendUpdateEvent, PLAYER_SIZE};
 use crate::schedule::{StartupSet, UpdateSet};
-use crate::socket::connection::{connect_socket, create_channel, get_socket_url};
+use crate::socket::connection::{connect_socket, create_channel};
+use crate::terrain::tile_map::TileMap;
 use bevy::prelude::*;
 use bevy::text::BreakLineOn;
-use tokio::sync::mpsc::Receiver;
+use bevy::utils::HashSet;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{Receiver, Sender};
 
 pub const GAME_ROOM: &str = "iso";
 pub const HEARTBEAT_INTERVAL_SECS: f32 = 15.0;
+// Consider the connection stale once this many heartbeats in a row went unanswered
+pub const MAX_MISSED_HEARTBEATS: u32 = 2;
+pub const CHANNEL_CAPACITY: usize = 256;
+// Most socket events handled in a single frame, the rest wait for the next one
+pub const EVENTS_PER_FRAME: usize = 512;
 
 #[derive(Debug, Resource)]
 pub struct Socket {
@@ -26,38 +44,234 @@ pub struct Socket {
     pub rx: Receiver<SocketEvent>,
     pub status: Option<SocketStatus>,
     pub last_response: Option<Response>,
-    _runtime: tokio::runtime::Runtime,
+    // Rooms to (re)join whenever the connection is established
+    pub rooms: HashSet<String>,
+    pub has_connected: bool,
+    // Consecutive reconnect attempts since the last connection
+    pub reconnect_attempts: usize,
+    pub overflow: OverflowBuffer,
+    pub metrics: SocketMetrics,
+    // Kept to start a new client on the same channel when reconnecting
+    tx: Sender<SocketEvent>,
+    pending: PendingRequests,
+    config: Config,
+    runtime: tokio::runtime::Runtime,
 }
 
 impl Socket {
-    pub fn new() -> Self {
-        let runtime = tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-            .unwrap();
+    pub fn new(config: Config) -> Self {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        if let Some(worker_threads) = config.worker_threads {
+            builder.worker_threads(worker_threads);
+        }
+        let runtime = builder.enable_all().build().unwrap();
 
         debug!("create_channel");
-        let (tx, rx) = create_channel();
-
-        let (handle, future) = runtime.block_on(async move {
-            let socket_url = get_socket_url();
-            debug!("connect_socket={:?}", &socket_url);
-            let (handle, future) = connect_socket(tx).await;
-            (handle, future)
-        });
+        let (tx, rx) = create_channel(config.channel_capacity);
+        let pending = PendingRequests::default();
+        let overflow = OverflowBuffer::default();
+        let metrics = SocketMetrics::default();
+        runtime.spawn(expire_pending(pending.clone(), tx.clone()));
 
-        runtime.spawn(async move {
-            future.await.unwrap();
-        });
+        let handle = connect_socket(
+            runtime.handle(),
+            tx.clone(),
+            config.clone(),
+            pending.clone(),
+            overflow.clone(),
+            metrics.clone(),
+        );
 
         Self {
             handle,
             rx,
             status: None,
             last_response: None,
-            _runtime: runtime,
+            rooms: [GAME_ROOM.to_string()].into_iter().collect(),
+            has_connected: false,
+            reconnect_attempts: 0,
+            overflow,
+            metrics,
+            tx,
+            pending,
+            config,
+            runtime,
+        }
+    }
+
+    // Close the current connection and connect again with a new client on the same runtime and
+    // channel. The new client's Connect event rejoins the rooms.
+    pub fn reconnect(&mut self) {
+        info!("reconnecting");
+
+        if let Err(e) = self.handle.close(None) {
+            debug!("error closing stale socket: {e:?}");
+        }
+        self.handle = connect_socket(
+            self.runtime.handle(),
+            self.tx.clone(),
+            self.config.clone(),
+            self.pending.clone(),
+            self.overflow.clone(),
+            self.metrics.clone(),
+        );
+    }
+
+    // Next queued event, skipping player updates older than one already applied from the
+    // overflow buffer
+    pub fn next_event(&mut self) -> Option<SocketEvent> {
+        loop {
+            let socket_event = self.rx.try_recv().ok()?;
+            let SocketEvent::Response(Response::PlayerUpdate(player_update)) = socket_event else {
+                return Some(socket_event);
+            };
+
+            match self.overflow.receive(player_update) {
+                Some(player_update) => {
+                    return Some(SocketEvent::Response(Response::PlayerUpdate(player_update)))
+                }
+                None => self.metrics.record_coalesced(),
+            }
         }
     }
+
+    // Mark the connection lost and schedule the next reconnect attempt, unless attempts ran out.
+    // The delay is waited out by a timer on the socket's runtime rather than in the client, so
+    // nothing is blocked in the meantime.
+    pub fn handle_connection_lost(&mut self, status: SocketStatus, policy: &ReconnectPolicy) {
+        self.status = Some(status);
+        self.reconnect_attempts += 1;
+
+        if !policy.should_reconnect(self.reconnect_attempts) {
+            error!(
+                "giving up after {} reconnect attempts",
+                self.reconnect_attempts - 1
+            );
+            return;
+        }
+
+        let delay = policy.delay_for_attempt(self.reconnect_attempts);
+        info!(
+            "reconnecting in {delay:?} (attempt {})",
+            self.reconnect_attempts
+        );
+        let tx = self.tx.clone();
+        self.runtime.spawn(async move {
+            tokio::time::sleep(delay).await;
+            // Fails only once the socket is gone
+            let _ = tx.send(SocketEvent::Reconnect).await;
+        });
+    }
+
+    // Connect again once a scheduled reconnect is due, unless a forced one already did
+    pub fn handle_reconnect(&mut self) {
+        if matches!(
+            self.status,
+            Some(SocketStatus::Closed | SocketStatus::ConnectFailed | SocketStatus::Disconnected)
+        ) {
+            self.reconnect();
+        }
+    }
+
+    // Mark the socket connected and (re)join every room the player is in
+    pub fn handle_connect(&mut self, store: &mut PlayerStore) {
+        self.status = Some(SocketStatus::Connected);
+        self.reconnect_attempts = 0;
+
+        // Friends will be re-sent in presence_state once we have rejoined
+        if self.has_connected {
+            store.remove_friends();
+        }
+        self.has_connected = true;
+
+        self.rejoin_rooms(store.get_player());
+    }
+
+    // Join every room the player is in, and re-send their last known position
+    fn rejoin_rooms(&self, player: &Player) {
+        for room in self.rooms.iter() {
+            let request = Request::new_join(room.clone(), player.clone());
+            self.handle.call(request).expect("join error");
+
+            if let Some(position) = player.position {
+                // Not tied to an input, so the server's reply is never reconciled
+                let request =
+                    Request::new_player_update(room.clone(), player.uuid.clone(), position, 0);
+                self.handle
+                    .call(request)
+                    .expect("player_update request error");
+            }
+        }
+    }
+}
+
+// Apply a server response to the store, returning the friend positions that changed.
+// Every position is passed through `constrain` first, e.g. to keep friends on the terrain.
+pub fn apply_response(
+    store: &mut PlayerStore,
+    response: Response,
+    constrain: impl Fn(Vec3) -> Vec3,
+) -> Vec<FriendUpdateEvent> {
+    let mut friend_updates = Vec::new();
+
+    match response {
+        Response::PlayerUpdate(player_update) => {
+            let position = constrain(player_update.position);
+            store.update_player_position(player_update.player_uuid.clone(), position);
+            friend_updates.push(FriendUpdateEvent::new(player_update.player_uuid, position));
+        }
+        Response::PresenceDiff(diff) => {
+            for mut player in diff.joins {
+                player.position = player.position.map(&constrain);
+                store.upsert_player(player.clone());
+                if let Some(position) = player.position {
+                    friend_updates.push(FriendUpdateEvent::new(player.uuid, position));
+                }
+            }
+            for player in diff.leaves {
+                store.remove_friend(player);
+            }
+        }
+        Response::PresenceState(mut state) => {
+            for player in state.players.iter_mut() {
+                player.position = player.position.map(&constrain);
+            }
+            store.upsert_players(state.players.clone());
+            for player in state.players {
+                if let Some(position) = player.position {
+                    friend_updates.push(FriendUpdateEvent::new(player.uuid, position));
+                }
+            }
+        }
+        Response::Shout(_shout) => (),
+        _ => (),
+    }
+
+    friend_updates
+}
+
+// Sent for every request expecting a reply once the server replies to it, or once it times out
+#[derive(Event, Debug)]
+pub struct ReplyEvent {
+    pub reply: Reply,
+}
+
+impl ReplyEvent {
+    pub fn new(reply: Reply) -> Self {
+        Self { reply }
+    }
+}
+
+// Sent for every response from the server, once it is applied to the store
+#[derive(Event, Debug)]
+pub struct ResponseEvent {
+    pub response: Response,
+}
+
+impl ResponseEvent {
+    pub fn new(response: Response) -> Self {
+        Self { response }
+    }
 }
 
 #[derive(Component)]
@@ -69,38 +283,86 @@ struct SocketInfo {
 #[reflect(Resource)]
 pub struct HeartbeatTimer {
     timer: Timer,
+    // When the heartbeat still waiting for its ack was sent
+    sent_at: Option<Instant>,
+    pub missed: u32,
+    pub round_trip: Option<Duration>,
 }
 
 impl Default for HeartbeatTimer {
     fn default() -> Self {
         Self {
             timer: Timer::from_seconds(HEARTBEAT_INTERVAL_SECS, TimerMode::Repeating),
+            sent_at: None,
+            missed: 0,
+            round_trip: None,
         }
     }
 }
 
 #[derive(Clone, Debug)]
-pub struct SocketPlugin {}
+pub struct Config {
+    pub reconnect_policy: ReconnectPolicy,
+    // Encoding for player updates, all other messages are always sent as JSON
+    pub encoding: Encoding,
+    // Worker threads for the socket's runtime, None = one per core
+    pub worker_threads: Option<usize>,
+    // Server to connect to, overrides the DEV and URL env vars when set
+    pub url: Option<String>,
+    // Size of the channel between the socket client and the game
+    pub channel_capacity: usize,
+    pub events_per_frame: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            reconnect_policy: ReconnectPolicy::default(),
+            encoding: Encoding::default(),
+            worker_threads: None,
+            url: None,
+            channel_capacity: CHANNEL_CAPACITY,
+            events_per_frame: EVENTS_PER_FRAME,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SocketPlugin {
+    pub config: Config,
+}
 
 impl Default for SocketPlugin {
     fn default() -> Self {
-        Self {}
+        Self {
+            config: Config::default(),
+        }
     }
 }
 
 impl Plugin for SocketPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(Socket::new())
+        app.insert_resource(Socket::new(self.config.clone()))
+            .insert_resource(self.config.reconnect_policy.clone())
             .insert_resource(HeartbeatTimer::default())
+            .register_type::<ReconnectPolicy>()
             .register_type::<HeartbeatTimer>()
+            .add_event::<ReplyEvent>()
+            .add_event::<ResponseEvent>()
             .add_systems(Startup, spawn_socket_info.in_set(StartupSet::SpawnEntities))
             .add_systems(
                 Update,
-                (handle_socket_events, update_socket_info)
+                (handle_socket_events, log_failed_replies, update_socket_info)
                     .chain()
                     .in_set(UpdateSet::AfterEffects),
             )
-            .add_systems(Update, send_heartbeat.in_set(UpdateSet::AfterEffects));
+            .add_systems(
+                Update,
+                (track_heartbeat_replies, send_heartbeat)
+                    .chain()
+                    .after(handle_socket_events)
+                    .in_set(UpdateSet::AfterEffects),
+            );
     }
 }
 
@@ -147,63 +409,89 @@ fn spawn_socket_info(mut commands: Commands, asset_server: Res<AssetServer>) {
         });
 }
 
+// Handle every queued socket event, up to the events_per_frame budget
 fn handle_socket_events(
     mut socket: ResMut<Socket>,
     mut store: ResMut<PlayerStore>,
     mut update_event_writer: EventWriter<FriendUpdateEvent>,
+    mut reply_event_writer: EventWriter<ReplyEvent>,
+    mut response_event_writer: EventWriter<ResponseEvent>,
+    reconnect_policy: Res<ReconnectPolicy>,
+    tile_map: Option<Res<TileMap>>,
 ) {
-    match socket.rx.try_recv() {
-        Ok(socket_event) => match socket_event {
-            SocketEvent::Close => socket.status = Some(SocketStatus::Closed),
-            SocketEvent::Connect => {
-                socket.status = Some(SocketStatus::Connected);
-                let request = Request::new_join(GAME_ROOM.into(), store.get_player().clone());
-                socket.handle.call(request).expect("join error");
+    // Positions from the server that land off the map are clamped back onto it
+    let constrain = |position| match tile_map.as_deref() {
+        Some(tile_map) => tile_map.clamp(position, PLAYER_SIZE / 2.0),
+        None => position,
+    };
+
+    for _ in 0..socket.config.events_per_frame {
+        let Some(socket_event) = socket.next_event() else {
+            break;
+        };
+
+        match socket_event {
+            SocketEvent::Close => {
+                socket.handle_connection_lost(SocketStatus::Closed, &reconnect_policy)
+            }
+            SocketEvent::Connect => socket.handle_connect(&mut store),
+            SocketEvent::ConnectFail => {
+                socket.handle_connection_lost(SocketStatus::ConnectFailed, &reconnect_policy)
+            }
+            SocketEvent::Disconnect => {
+                socket.handle_connection_lost(SocketStatus::Disconnected, &reconnect_policy)
+            }
+            SocketEvent::Reconnect => socket.handle_reconnect(),
+            SocketEvent::Reply(reply) => {
+                reply_event_writer.send(ReplyEvent::new(reply));
             }
-            SocketEvent::ConnectFail => socket.status = Some(SocketStatus::ConnectFailed),
-            SocketEvent::Disconnect => socket.status = Some(SocketStatus::Disconnected),
             SocketEvent::Response(response) => {
                 socket.last_response = Some(response.clone());
+                response_event_writer.send(ResponseEvent::new(response.clone()));
+                update_event_writer.send_batch(apply_response(&mut store, response, constrain));
+            }
+        }
+    }
 
-                match response {
-                    Response::PlayerUpdate(player_update) => {
-                        store.update_player_position(
-                            player_update.player_uuid.clone(),
-                            player_update.position.clone(),
-                        );
-                        update_event_writer.send(FriendUpdateEvent::new(
-                            player_update.player_uuid,
-                            player_update.position,
-                        ));
-                    }
-                    Response::PresenceDiff(diff) => {
-                        for player in diff.joins {
-                            store.upsert_player(player.clone());
-                            if let Some(position) = player.position {
-                                update_event_writer
-                                    .send(FriendUpdateEvent::new(player.uuid, position));
-                            }
-                        }
-                        for player in diff.leaves {
-                            store.remove_friend(player);
-                        }
-                    }
-                    Response::PresenceState(state) => {
-                        store.upsert_players(state.players.clone());
-                        for player in state.players {
-                            if let Some(position) = player.position {
-                                update_event_writer
-                                    .send(FriendUpdateEvent::new(player.uuid, position));
-                            }
-                        }
-                    }
-                    Response::Shout(_shout) => (),
-                    _ => (),
-                }
+    if !socket.rx.is_empty() {
+        socket.metrics.record_deferred_frame();
+    }
+
+    // Overflowed updates are the latest for their players, whatever is left in the channel
+    for player_update in socket.overflow.drain() {
+        let response = Response::PlayerUpdate(player_update);
+        response_event_writer.send(ResponseEvent::new(response.clone()));
+        update_event_writer.send_batch(apply_response(&mut store, response, constrain));
+    }
+}
+
+fn log_failed_replies(mut reply_event_reader: EventReader<ReplyEvent>) {
+    for ReplyEvent { reply } in reply_event_reader.read() {
+        match &reply.result {
+            Err(ReplyError::Rejected { status, response }) => {
+                warn!(
+                    "{} to {} rejected: status={status} response={response}",
+                    reply.request.event(),
+                    reply.request.topic()
+                );
             }
-        },
-        Err(tokio::sync::mpsc::error::TryRecvError::Empty) => (),
-        Err(_error) => (),
+            Err(ReplyError::Timeout) => {
+                debug!(
+                    "{} to {} timed out (ref={})",
+                    reply.request.event(),
+                    reply.request.topic(),
+                    reply.message_ref
+                );
+            }
+            Ok(_) => {
+                debug!(
+                    "{} to {} ok in {:?}",
+                    reply.request.event(),
+                    reply.request.topic(),
+                    reply.round_trip
+                );
+            }
+        }
     }
 }
 
@@ -211,6 +499,7 @@ fn update_socket_info(
     mut socket_info_query: Query<(&mut Text, &SocketInfo), With<SocketInfo>>,
     socket: Res<Socket>,
     store: Res<PlayerStore>,
+    heartbeat_timer: Res<HeartbeatTimer>,
 ) {
     let Ok((mut text, socket_info)) = socket_info_query.get_single_mut() else {
         return;
@@ -221,6 +510,11 @@ fn update_socket_info(
         None => "None".to_string(),
     };
 
+    let round_trip = match heartbeat_timer.round_trip {
+        Some(round_trip) => format!("{}ms", round_trip.as_millis()),
+        None => "-".to_string(),
+    };
+
     let player = store.get_player();
 
     let player_position = match player.position {
@@ -240,8 +534,9 @@ fn update_socket_info(
         })
         .collect();
 
+    let overflowed = socket.metrics.overflowed();
     let info_text = format!(
-        "{socket_status} @{} {} friends={:?}",
+        "{socket_status} rtt={round_trip} overflow={overflowed} @{} {} friends={:?}",
         player.username, player_position, friends_info
     );
 
@@ -250,15 +545,57 @@ fn update_socket_info(
     text.sections = vec![text_section];
 }
 
+// Heartbeats are the only requests sent to the phoenix topic, so its acks are theirs
+fn track_heartbeat_replies(
+    mut heartbeat_timer: ResMut<HeartbeatTimer>,
+    mut response_event_reader: EventReader<ResponseEvent>,
+) {
+    for ResponseEvent { response } in response_event_reader.read() {
+        let Response::Ack(ack) = response else {
+            continue;
+        };
+        if ack.status != "ok" {
+            continue;
+        }
+
+        if let Some(sent_at) = heartbeat_timer.sent_at.take() {
+            heartbeat_timer.round_trip = Some(sent_at.elapsed());
+        }
+        heartbeat_timer.missed = 0;
+    }
+}
+
 fn send_heartbeat(
     mut heartbeat_timer: ResMut<HeartbeatTimer>,
     time: Res<Time>,
-    socket: Res<Socket>,
+    mut socket: ResMut<Socket>,
 ) {
     heartbeat_timer.timer.tick(time.delta());
     if !heartbeat_timer.timer.just_finished() {
         return;
     }
+
+    // Only expect replies while connected, lost connections are reconnected on a timer otherwise
+    if socket.status != Some(SocketStatus::Connected) {
+        heartbeat_timer.sent_at = None;
+        return;
+    }
+
+    if heartbeat_timer.sent_at.is_some() {
+        heartbeat_timer.missed += 1;
+        warn!("missed heartbeat reply ({})", heartbeat_timer.missed);
+    }
+
+    if heartbeat_timer.missed >= MAX_MISSED_HEARTBEATS {
+        heartbeat_timer.sent_at = None;
+        heartbeat_timer.missed = 0;
+        // Shown until the new connection is made
+        socket.status = Some(SocketStatus::Stale);
+        socket.reconnect();
+        return;
+    }
+
     let request = Request::new_heartbeat();
     socket.handle.call(request).expect("heartbeat error");
+    heartbeat_timer.sent_at = Some(Instant::now());
 }
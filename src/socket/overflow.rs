@@ -0,0 +1,223 @@
+use super::client::SocketEvent;
+use super::metrics::SocketMetrics;
+use super::response::{PlayerUpdate, Response};
+use bevy::log::prelude::*;
+use bevy::utils::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// This module contains the `OverflowBuffer`, where the client puts player updates that did not
+/// fit in the socket channel. Only the latest update per player is kept, and the game drains the
+/// buffer every frame, so a saturated channel delays friend positions by at most a frame instead
+/// of queueing every stale one.
+/// A buffered update is newer than any update for the same player still queued in the channel,
+/// so once it is applied those queued updates are dropped as they come out. Updates are counted
+/// as they go into and out of the channel to tell which ones were queued before it.
+
+#[derive(Clone, Debug, Default)]
+pub struct OverflowBuffer {
+    state: Arc<Mutex<OverflowState>>,
+}
+
+#[derive(Debug, Default)]
+struct OverflowState {
+    // Latest overflowed update per player, with the number of updates sent to the channel before it
+    updates: HashMap<String, (usize, PlayerUpdate)>,
+    // Players whose buffered update was applied while older updates for them were still queued,
+    // with the number of updates sent to the channel before it
+    applied: HashMap<String, usize>,
+    // Player updates that went into the channel, and that came out of it
+    sent: usize,
+    received: usize,
+}
+
+impl OverflowBuffer {
+    // Send a player update to the channel without waiting for room, buffering it if there is none
+    pub fn send(
+        &self,
+        tx: &mpsc::Sender<SocketEvent>,
+        player_update: PlayerUpdate,
+        metrics: &SocketMetrics,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let player_uuid = player_update.player_uuid.clone();
+
+        let event = SocketEvent::Response(Response::PlayerUpdate(player_update));
+        match tx.try_send(event) {
+            Ok(()) => {
+                state.sent += 1;
+                // The update just sent supersedes the one buffered for this player
+                if state.updates.remove(&player_uuid).is_some() {
+                    metrics.record_coalesced();
+                }
+            }
+            Err(TrySendError::Full(SocketEvent::Response(Response::PlayerUpdate(
+                player_update,
+            )))) => {
+                metrics.record_overflow();
+                let sent = state.sent;
+                if state
+                    .updates
+                    .insert(player_uuid, (sent, player_update))
+                    .is_some()
+                {
+                    metrics.record_coalesced();
+                }
+            }
+            Err(TrySendError::Full(_)) => (),
+            Err(TrySendError::Closed(_)) => error!("error sending message to channel: closed"),
+        }
+    }
+
+    // Count a player update taken from the channel, returning it unless a newer one for the
+    // same player was already applied from the buffer
+    pub fn receive(&self, player_update: PlayerUpdate) -> Option<PlayerUpdate> {
+        let mut state = self.state.lock().unwrap();
+        let index = state.received;
+        state.received += 1;
+
+        let Some(&sent_before) = state.applied.get(&player_update.player_uuid) else {
+            return Some(player_update);
+        };
+        // Past the last update queued before the buffered one, nothing is stale anymore
+        if index + 1 >= sent_before {
+            state.applied.remove(&player_update.player_uuid);
+        }
+
+        if index < sent_before {
+            None
+        } else {
+            Some(player_update)
+        }
+    }
+
+    // Take every buffered update, to be applied after the events received this frame
+    pub fn drain(&self) -> Vec<PlayerUpdate> {
+        let mut state = self.state.lock().unwrap();
+        let updates: Vec<(String, (usize, PlayerUpdate))> = state.updates.drain().collect();
+
+        updates
+            .into_iter()
+            .map(|(player_uuid, (sent_before, player_update))| {
+                if sent_before > state.received {
+                    state.applied.insert(player_uuid, sent_before);
+                }
+                player_update
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::math::Vec3;
+    use tokio::sync::mpsc::Receiver;
+
+    const CHANNEL_CAPACITY: usize = 4;
+    const EVENTS_PER_FRAME: usize = 2;
+
+    fn player_update(player_uuid: &str, x: f32) -> PlayerUpdate {
+        PlayerUpdate {
+            player_uuid: player_uuid.to_string(),
+            position: Vec3::new(x, 0.0, 0.0),
+            seq: 0,
+        }
+    }
+
+    // What the game does every frame: take events up to its budget, then drain the buffer
+    fn run_frame(
+        rx: &mut Receiver<SocketEvent>,
+        overflow: &OverflowBuffer,
+        positions: &mut HashMap<String, f32>,
+    ) {
+        let mut apply = |player_update: PlayerUpdate| {
+            let previous =
+                positions.insert(player_update.player_uuid.clone(), player_update.position.x);
+            assert!(
+                !previous.is_some_and(|previous| previous > player_update.position.x),
+                "{} moved back from {previous:?} to {}",
+                player_update.player_uuid,
+                player_update.position.x
+            );
+        };
+
+        for _ in 0..EVENTS_PER_FRAME {
+            let Ok(SocketEvent::Response(Response::PlayerUpdate(player_update))) = rx.try_recv()
+            else {
+                break;
+            };
+            if let Some(player_update) = overflow.receive(player_update) {
+                apply(player_update);
+            }
+        }
+        for player_update in overflow.drain() {
+            apply(player_update);
+        }
+    }
+
+    #[test]
+    fn saturated_channel_keeps_positions_current() {
+        let (tx, mut rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let overflow = OverflowBuffer::default();
+        let metrics = SocketMetrics::default();
+        let mut positions = HashMap::new();
+
+        // Every frame brings more updates than the game handles, so the channel never empties
+        let mut x = 0.0;
+        for _ in 0..50 {
+            for _ in 0..5 {
+                x += 1.0;
+                overflow.send(&tx, player_update("a", x), &metrics);
+                overflow.send(&tx, player_update("b", x), &metrics);
+            }
+            run_frame(&mut rx, &overflow, &mut positions);
+
+            assert_eq!(positions["a"], x);
+            assert_eq!(positions["b"], x);
+        }
+
+        assert!(metrics.overflowed() > 0);
+        assert!(metrics.coalesced() > 0);
+        assert!(!rx.is_empty());
+    }
+
+    #[test]
+    fn queued_updates_older_than_an_applied_one_are_dropped() {
+        let (tx, mut rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let overflow = OverflowBuffer::default();
+        let metrics = SocketMetrics::default();
+        let mut positions = HashMap::new();
+
+        for x in 1..=6 {
+            overflow.send(&tx, player_update("a", x as f32), &metrics);
+        }
+        run_frame(&mut rx, &overflow, &mut positions);
+        assert_eq!(positions["a"], 6.0);
+
+        // The rest of the queue is older than what was applied
+        overflow.send(&tx, player_update("a", 7.0), &metrics);
+        for _ in 0..CHANNEL_CAPACITY {
+            run_frame(&mut rx, &overflow, &mut positions);
+        }
+        assert!(rx.is_empty());
+        assert_eq!(positions["a"], 7.0);
+    }
+
+    #[test]
+    fn update_sent_once_there_is_room_supersedes_the_buffered_one() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let overflow = OverflowBuffer::default();
+        let metrics = SocketMetrics::default();
+        let mut positions = HashMap::new();
+
+        overflow.send(&tx, player_update("a", 1.0), &metrics);
+        overflow.send(&tx, player_update("a", 2.0), &metrics);
+        rx.try_recv().unwrap();
+        overflow.send(&tx, player_update("a", 3.0), &metrics);
+
+        run_frame(&mut rx, &overflow, &mut positions);
+        assert_eq!(positions["a"], 3.0);
+        assert!(overflow.drain().is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+use bevy::prelude::*;
+use rand::Rng;
+use std::time::Duration;
+
+/// This module contains the `ReconnectPolicy` used by the socket when the connection is lost.
+/// Delays grow exponentially from `initial_delay` up to `max_delay`, with random jitter rolled
+/// for every attempt so that many clients dropped at once don't all reconnect at the same moment.
+
+#[derive(Clone, Debug, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f32,
+    // Fraction of the delay to randomly add or remove, e.g. 0.3 = +/- 30%
+    pub jitter: f32,
+    // Give up after this many consecutive failed attempts, None = retry forever
+    pub max_attempts: Option<usize>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.3,
+            max_attempts: Some(20),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn should_reconnect(&self, attempt: usize) -> bool {
+        match self.max_attempts {
+            Some(max_attempts) => attempt <= max_attempts,
+            None => true,
+        }
+    }
+
+    // Delay before the given attempt, starting at attempt 1, before jitter
+    pub fn base_delay(&self, attempt: usize) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as usize) as i32;
+        let delay = (self.initial_delay.as_secs_f32() * self.multiplier.powi(exponent))
+            .min(self.max_delay.as_secs_f32());
+
+        Duration::from_secs_f32(delay)
+    }
+
+    // Delay before the given attempt, with jitter rolled again on every call
+    pub fn delay_for_attempt(&self, attempt: usize) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = if jitter > 0.0 {
+            rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter)
+        } else {
+            1.0
+        };
+
+        self.base_delay(attempt).mul_f32(factor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_grows_by_the_multiplier() {
+        let policy = ReconnectPolicy::default();
+
+        assert_eq!(policy.base_delay(1), Duration::from_millis(500));
+        assert_eq!(policy.base_delay(2), Duration::from_secs(1));
+        assert_eq!(policy.base_delay(3), Duration::from_secs(2));
+        assert_eq!(policy.base_delay(5), Duration::from_secs(8));
+    }
+
+    #[test]
+    fn delay_is_capped_at_max_delay() {
+        let policy = ReconnectPolicy::default();
+
+        assert_eq!(policy.base_delay(7), policy.max_delay);
+        assert_eq!(policy.base_delay(1_000), policy.max_delay);
+        assert_eq!(policy.base_delay(usize::MAX), policy.max_delay);
+    }
+
+    #[test]
+    fn jitter_stays_in_bounds_and_is_rolled_per_attempt() {
+        let policy = ReconnectPolicy::default();
+        let base = policy.base_delay(3).as_secs_f32();
+
+        let delays: Vec<f32> = (0..100)
+            .map(|_| policy.delay_for_attempt(3).as_secs_f32())
+            .collect();
+        for delay in delays.iter() {
+            assert!(*delay >= base * 0.7 - 1e-3, "{delay} below {base}");
+            assert!(*delay <= base * 1.3 + 1e-3, "{delay} above {base}");
+        }
+        assert!(delays.iter().any(|&delay| delay != delays[0]));
+
+        let without_jitter = ReconnectPolicy {
+            jitter: 0.0,
+            ..default()
+        };
+        assert_eq!(
+            without_jitter.delay_for_attempt(3),
+            without_jitter.base_delay(3)
+        );
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let policy = ReconnectPolicy {
+            max_attempts: Some(3),
+            ..default()
+        };
+        assert!(policy.should_reconnect(1));
+        assert!(policy.should_reconnect(3));
+        assert!(!policy.should_reconnect(4));
+
+        let forever = ReconnectPolicy {
+            max_attempts: None,
+            ..default()
+        };
+        assert!(forever.should_reconnect(usize::MAX));
+    }
+}
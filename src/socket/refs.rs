@@ -1,27 +1,65 @@
-use crate::helpers::names::generate_uuid;
+use super::request::Request;
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
-#[derive(Debug)]
-pub struct Refs {
-    pub join_ref: String,
-    pub message_ref: AtomicUsize,
-}
+/// This module allocates the refs sent with every message:
+///   - `join_ref` is allocated once per channel join and sent with every message on that channel
+///   - `message_ref` keeps increasing for the life of the client, across clones of the allocator
 
-impl Default for Refs {
-    fn default() -> Self {
-        Self {
-            join_ref: generate_uuid(),
-            message_ref: AtomicUsize::new(1),
-        }
-    }
+#[derive(Clone, Debug, PartialEq)]
+pub struct Refs {
+    pub join_ref: Option<String>,
+    pub message_ref: usize,
 }
 
 impl Refs {
-    pub fn get_join_ref(&self) -> String {
+    pub fn get_join_ref(&self) -> Option<String> {
         self.join_ref.clone()
     }
 
     pub fn get_message_ref(&self) -> usize {
-        self.message_ref.load(Ordering::SeqCst)
+        self.message_ref
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RefAllocator {
+    message_ref: Arc<AtomicUsize>,
+    join_refs: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl RefAllocator {
+    pub fn next_message_ref(&self) -> usize {
+        self.message_ref.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    // Allocate refs for a request. Like phoenix.js, a join uses its own message_ref as the
+    // channel's join_ref. Heartbeats are not sent on a joined channel, so they get no join_ref.
+    pub fn next_refs(&self, request: &Request) -> Refs {
+        let message_ref = self.next_message_ref();
+        let topic = request.topic();
+        let mut join_refs = self.join_refs.lock().unwrap();
+
+        let join_ref = match request {
+            Request::Heartbeat => None,
+            Request::Join { .. } => {
+                let join_ref = message_ref.to_string();
+                join_refs.insert(topic, join_ref.clone());
+                Some(join_ref)
+            }
+            Request::Leave { .. } => join_refs.remove(&topic),
+            _ => join_refs.get(&topic).cloned(),
+        };
+
+        Refs {
+            join_ref,
+            message_ref,
+        }
+    }
+
+    // Forget all channel joins, e.g. after the connection was re-established
+    pub fn clear_join_refs(&self) {
+        self.join_refs.lock().unwrap().clear();
     }
 }
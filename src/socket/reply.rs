@@ -0,0 +1,233 @@
+use super::message::Message;
+use super::request::Request;
+use serde::Deserialize;
+use serde_json::Value as SerdeValue;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// This module tracks requests sent to the server by their `message_ref`.
+/// A pending request is resolved when the matching `phx_reply` arrives, or expires after a timeout.
+/// Only requests whose reply someone waits for are tracked, see `Request::expects_reply`.
+
+pub const REPLY_TIMEOUT_SECS: u64 = 10;
+// How often pending requests are checked for timeouts
+pub const EXPIRE_INTERVAL_MS: u64 = 500;
+
+#[derive(Clone, Debug)]
+pub struct Reply {
+    pub message_ref: usize,
+    pub request: Request,
+    pub round_trip: Duration,
+    pub result: Result<SerdeValue, ReplyError>,
+}
+
+#[derive(Clone, Debug)]
+pub enum ReplyError {
+    // Server replied with a non-ok status, e.g. a rejected join or a rate limit
+    Rejected {
+        status: String,
+        response: SerdeValue,
+    },
+    Timeout,
+}
+
+#[derive(Debug)]
+struct PendingRequest {
+    request: Request,
+    sent_at: Instant,
+}
+
+// Shared between the socket client, which tracks and resolves requests, and the task expiring them
+#[derive(Clone, Debug)]
+pub struct PendingRequests {
+    requests: Arc<Mutex<HashMap<usize, PendingRequest>>>,
+    timeout: Duration,
+}
+
+impl Default for PendingRequests {
+    fn default() -> Self {
+        Self {
+            requests: Arc::default(),
+            timeout: Duration::from_secs(REPLY_TIMEOUT_SECS),
+        }
+    }
+}
+
+impl PendingRequests {
+    pub fn track(&self, message_ref: usize, request: Request) {
+        self.requests.lock().unwrap().insert(
+            message_ref,
+            PendingRequest {
+                request,
+                sent_at: Instant::now(),
+            },
+        );
+    }
+
+    // Resolve the pending request matching a phx_reply message, if any
+    pub fn resolve(&self, message: &Message) -> Option<Reply> {
+        if message.event != "phx_reply" {
+            return None;
+        }
+
+        let message_ref = message.message_ref?;
+        let pending = self.requests.lock().unwrap().remove(&message_ref)?;
+
+        let payload = message.payload.as_json().cloned().unwrap_or_default();
+        let result = match RawReply::deserialize(&payload) {
+            Ok(RawReply { status, response }) if status == "ok" => Ok(response),
+            Ok(RawReply { status, response }) => Err(ReplyError::Rejected { status, response }),
+            Err(_) => Err(ReplyError::Rejected {
+                status: "invalid".to_string(),
+                response: payload,
+            }),
+        };
+
+        Some(Reply {
+            message_ref,
+            request: pending.request,
+            round_trip: pending.sent_at.elapsed(),
+            result,
+        })
+    }
+
+    // Remove and return all pending requests that have waited longer than the timeout
+    pub fn expire(&self) -> Vec<Reply> {
+        let mut requests = self.requests.lock().unwrap();
+        let expired_refs: Vec<usize> = requests
+            .iter()
+            .filter(|(_, pending)| pending.sent_at.elapsed() >= self.timeout)
+            .map(|(message_ref, _)| *message_ref)
+            .collect();
+
+        expired_refs
+            .into_iter()
+            .filter_map(|message_ref| {
+                let pending = requests.remove(&message_ref)?;
+                Some(Reply {
+                    message_ref,
+                    request: pending.request,
+                    round_trip: pending.sent_at.elapsed(),
+                    result: Err(ReplyError::Timeout),
+                })
+            })
+            .collect()
+    }
+
+    // Forget every pending request, e.g. when they were sent over a connection that is gone
+    pub fn clear(&self) {
+        self.requests.lock().unwrap().clear();
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct RawReply {
+    status: String,
+    #[serde(default)]
+    response: SerdeValue,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::message::Payload;
+    use serde_json::json;
+
+    fn reply(message_ref: usize, payload: SerdeValue) -> Message {
+        Message {
+            message_ref: Some(message_ref),
+            topic: "game:iso".to_string(),
+            event: "phx_reply".to_string(),
+            payload: Payload::Json(payload),
+            ..Default::default()
+        }
+    }
+
+    fn leave() -> Request {
+        Request::new_leave("iso".to_string())
+    }
+
+    #[test]
+    fn ok_reply_resolves_the_request() {
+        let pending = PendingRequests::default();
+        pending.track(1, leave());
+
+        let resolved = pending
+            .resolve(&reply(1, json!({"status": "ok", "response": {"id": 7}})))
+            .unwrap();
+        assert_eq!(resolved.message_ref, 1);
+        assert_eq!(resolved.request.event(), "phx_leave");
+        assert_eq!(resolved.result.unwrap(), json!({"id": 7}));
+
+        // Resolved requests are forgotten
+        assert!(pending
+            .resolve(&reply(1, json!({"status": "ok"})))
+            .is_none());
+    }
+
+    #[test]
+    fn error_reply_is_rejected() {
+        let pending = PendingRequests::default();
+        pending.track(1, leave());
+
+        let resolved = pending
+            .resolve(&reply(
+                1,
+                json!({"status": "error", "response": {"reason": "full"}}),
+            ))
+            .unwrap();
+        assert!(matches!(
+            resolved.result,
+            Err(ReplyError::Rejected { status, response })
+                if status == "error" && response == json!({"reason": "full"})
+        ));
+    }
+
+    #[test]
+    fn unknown_ref_or_other_event_is_not_resolved() {
+        let pending = PendingRequests::default();
+        pending.track(1, leave());
+
+        assert!(pending
+            .resolve(&reply(2, json!({"status": "ok"})))
+            .is_none());
+        let push = Message {
+            event: "shout".to_string(),
+            ..reply(1, json!({"status": "ok"}))
+        };
+        assert!(pending.resolve(&push).is_none());
+        assert!(pending
+            .resolve(&reply(1, json!({"status": "ok"})))
+            .is_some());
+    }
+
+    #[test]
+    fn expire_returns_only_timed_out_requests() {
+        let pending = PendingRequests::default();
+        pending.track(1, leave());
+        pending.track(2, leave());
+        let timeout = Duration::from_secs(REPLY_TIMEOUT_SECS);
+        pending
+            .requests
+            .lock()
+            .unwrap()
+            .get_mut(&1)
+            .unwrap()
+            .sent_at -= timeout;
+
+        let expired = pending.expire();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].message_ref, 1);
+        assert!(matches!(expired[0].result, Err(ReplyError::Timeout)));
+
+        // The request still waiting can be resolved, the expired one can't
+        assert!(pending.expire().is_empty());
+        assert!(pending
+            .resolve(&reply(1, json!({"status": "ok"})))
+            .is_none());
+        assert!(pending
+            .resolve(&reply(2, json!({"status": "ok"})))
+            .is_some());
+    }
+}
@@ -1,79 +1,210 @@
-use super::{message::Message as SocketMessage, refs::Refs};
+use super::message::{Encoding, Message as SocketMessage, Payload};
+use super::refs::Refs;
 use crate::player::player::Player;
 use bevy::prelude::*;
-use serde_json::{json, Value as SerdeValue};
+use serde::ser::Error as _;
+use serde::Serialize;
+use serde_json::{Error as SerdeError, Result as SerdeResult};
 
-// This module contains the Request struct used to create requests to be sent to the server.
+// This module contains the Request enum used to create requests to be sent to the server.
+// Each variant carries a typed payload, so a malformed outgoing message fails to compile.
 
-const TOPIC_PREFIX: &str = "game:";
+pub const TOPIC_PREFIX: &str = "game:";
+const PHOENIX_TOPIC: &str = "phoenix";
 
-// TODO: maybe Request should be an enum, e.g. Heartbeat, Join, Leave, Shout
 #[derive(Clone, Debug)]
-pub struct Request {
-    pub event: String,
-    payload: SerdeValue,
-    topic: String,
+pub enum Request {
+    Heartbeat,
+    Join {
+        room: String,
+        payload: JoinPayload,
+    },
+    Leave {
+        room: String,
+    },
+    Shout {
+        room: String,
+        payload: ShoutPayload,
+    },
+    PlayerUpdate {
+        room: String,
+        payload: PlayerUpdatePayload,
+    },
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct EmptyPayload {}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct JoinPayload {
+    pub player: Player,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ShoutPayload {
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PlayerUpdatePayload {
+    pub player_uuid: String,
+    pub position: Vec3,
+    // Sequence id of the last movement input applied to this position
+    pub seq: u32,
+}
+
+impl PlayerUpdatePayload {
+    // Compact binary layout: [uuid_size][uuid][x][y][z][seq],
+    // with coordinates as little-endian f32 and seq as little-endian u32.
+    // Fails if the uuid is too long for its size to fit in a byte.
+    pub fn to_bytes(&self) -> SerdeResult<Vec<u8>> {
+        let uuid = self.player_uuid.as_bytes();
+        let uuid_size = u8::try_from(uuid.len()).map_err(|_| {
+            SerdeError::custom(format!("player_uuid too long: {}", self.player_uuid))
+        })?;
+
+        let mut bytes = Vec::with_capacity(1 + uuid.len() + 16);
+        bytes.push(uuid_size);
+        bytes.extend_from_slice(uuid);
+        for coordinate in self.position.to_array() {
+            bytes.extend_from_slice(&coordinate.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.seq.to_le_bytes());
+        Ok(bytes)
+    }
 }
 
 impl Request {
-    #[allow(dead_code)]
     pub fn new_heartbeat() -> Self {
-        Self {
-            topic: "phoenix".to_string(),
-            event: "heartbeat".to_string(),
-            payload: json!({}),
-        }
+        Self::Heartbeat
     }
 
     pub fn new_join(room: String, player: Player) -> Self {
-        Self {
-            topic: room_to_topic(room),
-            event: "phx_join".to_string(),
-            payload: json!({ "player": player  }),
+        Self::Join {
+            room,
+            payload: JoinPayload { player },
         }
     }
 
     #[allow(dead_code)]
     pub fn new_leave(room: String) -> Self {
-        Self {
-            topic: room_to_topic(room),
-            event: "phx_leave".to_string(),
-            payload: json!({}),
-        }
+        Self::Leave { room }
     }
 
     #[allow(dead_code)]
     pub fn new_shout(room: String, message: String) -> Self {
-        Self {
-            topic: room_to_topic(room),
-            event: "shout".to_string(),
-            payload: json!({  "message": message }),
+        Self::Shout {
+            room,
+            payload: ShoutPayload { message },
         }
     }
 
-    pub fn new_player_update(room: String, uuid: String, new_position: Vec3) -> Self {
-        Self {
-            topic: room_to_topic(room),
-            event: "player_update".to_string(),
-            payload: json!({ "player_uuid": uuid, "position": new_position }),
+    pub fn new_player_update(room: String, uuid: String, new_position: Vec3, seq: u32) -> Self {
+        Self::PlayerUpdate {
+            room,
+            payload: PlayerUpdatePayload {
+                player_uuid: uuid,
+                position: new_position,
+                seq,
+            },
         }
     }
 
-    pub fn to_payload(&self, refs: Refs) -> String {
-        let message = SocketMessage {
-            join_ref: Some(refs.get_join_ref()),
-            message_ref: Some(refs.get_message_ref()),
-            topic: self.topic.clone(),
-            event: self.event.clone(),
-            payload: self.payload.clone(),
+    pub fn topic(&self) -> String {
+        match self {
+            Self::Heartbeat => PHOENIX_TOPIC.to_string(),
+            Self::Join { room, .. }
+            | Self::Leave { room }
+            | Self::Shout { room, .. }
+            | Self::PlayerUpdate { room, .. } => room_to_topic(room),
+        }
+    }
+
+    pub fn event(&self) -> &'static str {
+        match self {
+            Self::Heartbeat => "heartbeat",
+            Self::Join { .. } => "phx_join",
+            Self::Leave { .. } => "phx_leave",
+            Self::Shout { .. } => "shout",
+            Self::PlayerUpdate { .. } => "player_update",
+        }
+    }
+
+    // Whether a missing reply is worth a timeout. Heartbeat acks are checked by the
+    // `HeartbeatTimer`, and player update acks are only used to reconcile, where a newer
+    // update supersedes one that was never acknowledged.
+    pub fn expects_reply(&self) -> bool {
+        !matches!(self, Self::Heartbeat | Self::PlayerUpdate { .. })
+    }
+
+    fn payload(&self, encoding: Encoding) -> Payload {
+        // Updates that don't fit the binary layout are sent as JSON instead
+        if let (Self::PlayerUpdate { payload, .. }, Encoding::Binary) = (self, encoding) {
+            match payload.to_bytes() {
+                Ok(bytes) => return Payload::Binary(bytes),
+                Err(e) => warn!("sending player_update as JSON: {e}"),
+            }
+        }
+
+        let payload = match self {
+            Self::Heartbeat | Self::Leave { .. } => serde_json::to_value(EmptyPayload::default()),
+            Self::Join { payload, .. } => serde_json::to_value(payload),
+            Self::Shout { payload, .. } => serde_json::to_value(payload),
+            Self::PlayerUpdate { payload, .. } => serde_json::to_value(payload),
         };
 
-        message
-            .serialize_to_json_string()
-            .expect("Problem serializing message")
+        Payload::Json(payload.expect("Problem serializing payload"))
+    }
+
+    // Build the Message sent over the socket for this request.
+    // Only player updates have a binary payload, everything else is always sent as JSON.
+    pub fn to_message(&self, refs: Refs, encoding: Encoding) -> SocketMessage {
+        SocketMessage {
+            join_ref: refs.get_join_ref(),
+            message_ref: Some(refs.get_message_ref()),
+            topic: self.topic(),
+            event: self.event().to_string(),
+            payload: self.payload(encoding),
+        }
     }
 }
 
-fn room_to_topic(room: String) -> String {
+fn room_to_topic(room: &str) -> String {
     format!("{TOPIC_PREFIX}{room}")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::response::PlayerUpdate;
+
+    fn player_update(player_uuid: String) -> Request {
+        Request::new_player_update("iso".to_string(), player_uuid, Vec3::new(1.0, 2.0, 3.0), 7)
+    }
+
+    #[test]
+    fn binary_player_update_round_trips() {
+        let Request::PlayerUpdate { payload, .. } = player_update("uuid".to_string()) else {
+            unreachable!();
+        };
+        let decoded = PlayerUpdate::from_bytes(&payload.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(decoded.player_uuid, payload.player_uuid);
+        assert_eq!(decoded.position, payload.position);
+        assert_eq!(decoded.seq, payload.seq);
+    }
+
+    #[test]
+    fn long_uuid_is_sent_as_json_instead_of_truncated() {
+        let request = player_update("u".repeat(300));
+        let Request::PlayerUpdate { payload, .. } = &request else {
+            unreachable!();
+        };
+
+        assert!(payload.to_bytes().is_err());
+        assert!(matches!(
+            request.payload(Encoding::Binary),
+            Payload::Json(value) if value["player_uuid"] == payload.player_uuid
+        ));
+    }
+}
@@ -1,71 +1,110 @@
-use super::message::Message;
+use super::message::{Message, Payload};
 use crate::player::player::Player;
 use bevy::math::Vec3;
+use serde::de::{DeserializeOwned, Error as _};
 use serde::{Deserialize, Serialize};
+use serde_json::Value as SerdeValue;
 use std::collections::HashMap;
+use std::fmt;
 
-/// This module contains logic for parsing JSON from the server.
-/// Response struct exposes a single `new_from_json_string` fn which takes a JSON string and returns a `Response` enum.
+/// This module contains logic for parsing messages from the server.
+/// Response struct exposes a `new_from_message` fn which takes a decoded `Message` and returns a `Response` enum,
+/// or a `DecodeError` if its payload could not be decoded.
 
 // The Response enum we will build based on the event type
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Debug)]
 pub enum Response {
     Ack(Ack),
     JoinReply(JoinReply),
     PlayerUpdate(PlayerUpdate),
+    PlayerUpdateAck(PlayerUpdateAck),
     PresenceDiff(PresenceDiff),
     PresenceState(PresenceState),
     RoomsUpdate(RoomsUpdate),
     Shout(Shout),
-    #[default]
-    Unknown,
+    // Events we don't know how to handle (yet) are passed through untouched
+    Unknown(Message),
 }
 
-impl Response {
-    pub fn new_from_json_string(json_data: &str) -> Self {
-        let Ok(message) = Message::new_from_json_string(json_data) else {
-            return Response::Unknown;
-        };
+#[derive(Debug)]
+pub struct DecodeError {
+    pub topic: String,
+    pub event: String,
+    pub error: serde_json::Error,
+}
+
+impl DecodeError {
+    fn new(message: &Message, error: serde_json::Error) -> Self {
+        Self {
+            topic: message.topic.clone(),
+            event: message.event.clone(),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to decode topic={} event={}: {}",
+            self.topic, self.event, self.error
+        )
+    }
+}
 
-        return match message.event.as_str() {
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl Response {
+    pub fn new_from_message(message: Message) -> Result<Self, DecodeError> {
+        let response = match message.event.as_str() {
             "phx_reply" => {
+                let null = SerdeValue::Null;
+                let payload = message.payload.as_json().unwrap_or(&null);
+
                 if message.topic == "phoenix" {
-                    if let Ok(reply) = serde_json::from_value::<RawAckReply>(message.payload) {
-                        return Response::Ack(Ack {
+                    if let Ok(reply) = RawAckReply::deserialize(payload) {
+                        return Ok(Response::Ack(Ack {
                             status: reply.status,
-                        });
+                        }));
                     }
-                } else {
-                    if let Ok(reply) = serde_json::from_value::<RawJoinReply>(message.payload) {
-                        if reply.response.event == "phx_join" {
-                            return Response::JoinReply(JoinReply {
-                                player: reply.response.player,
-                            });
-                        }
+                } else if let Ok(reply) = RawJoinReply::deserialize(payload) {
+                    if reply.response.event == "phx_join" {
+                        return Ok(Response::JoinReply(JoinReply {
+                            player: reply.response.player,
+                        }));
+                    }
+                } else if let Ok(reply) = RawPlayerUpdateAckReply::deserialize(payload) {
+                    if reply.status == "ok" {
+                        return Ok(Response::PlayerUpdateAck(reply.response));
                     }
                 }
-                Response::Unknown
-            }
-            "player_update" => {
-                let player_update =
-                    serde_json::from_value::<PlayerUpdate>(message.payload).unwrap();
-                Response::PlayerUpdate(player_update)
+                Response::Unknown(message)
             }
+            "player_update" => match &message.payload {
+                Payload::Binary(bytes) => Response::PlayerUpdate(
+                    PlayerUpdate::from_bytes(bytes)
+                        .map_err(|error| DecodeError::new(&message, error))?,
+                ),
+                Payload::Json(_) => Response::PlayerUpdate(decode_payload(&message)?),
+            },
             "presence_diff" => {
-                let raw_diff = serde_json::from_value::<RawPresenceDiff>(message.payload).unwrap();
+                let raw_diff: RawPresenceDiff = decode_payload(&message)?;
                 let joins = get_players(raw_diff.joins);
                 let leaves = get_players(raw_diff.leaves);
                 Response::PresenceDiff(PresenceDiff { joins, leaves })
             }
             "presence_state" => {
-                let raw_state =
-                    serde_json::from_value::<RawPresenceState>(message.payload).unwrap();
+                let raw_state: RawPresenceState = decode_payload(&message)?;
                 let players = get_players(raw_state);
                 Response::PresenceState(PresenceState { players })
             }
             "rooms_update" => {
-                let rooms_update =
-                    serde_json::from_value::<RawRoomsUpdate>(message.payload).unwrap();
+                let rooms_update: RawRoomsUpdate = decode_payload(&message)?;
                 let rooms: Vec<Room> = rooms_update
                     .rooms
                     .iter()
@@ -76,12 +115,11 @@ impl Response {
                     .collect();
                 Response::RoomsUpdate(rooms)
             }
-            "shout" => {
-                let shout = serde_json::from_value::<Shout>(message.payload).unwrap();
-                Response::Shout(shout)
-            }
-            _ => Response::Unknown,
+            "shout" => Response::Shout(decode_payload(&message)?),
+            _ => Response::Unknown(message),
         };
+
+        Ok(response)
     }
 }
 
@@ -99,6 +137,47 @@ pub struct JoinReply {
 pub struct PlayerUpdate {
     pub player_uuid: String,
     pub position: Vec3,
+    #[serde(default)]
+    pub seq: u32,
+}
+
+impl PlayerUpdate {
+    // Decode the compact binary layout written by `PlayerUpdatePayload::to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> serde_json::Result<Self> {
+        let too_short = || serde_json::Error::custom("player_update payload too short");
+
+        let uuid_size = *bytes.first().ok_or_else(too_short)? as usize;
+        let uuid_bytes = bytes.get(1..1 + uuid_size).ok_or_else(too_short)?;
+        let player_uuid =
+            String::from_utf8(uuid_bytes.to_vec()).map_err(serde_json::Error::custom)?;
+
+        let coordinates = bytes
+            .get(1 + uuid_size..1 + uuid_size + 12)
+            .ok_or_else(too_short)?;
+        let mut position = [0.0; 3];
+        for (coordinate, chunk) in position.iter_mut().zip(coordinates.chunks_exact(4)) {
+            *coordinate = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+
+        // Older clients don't send a seq
+        let seq = match bytes.get(1 + uuid_size + 12..1 + uuid_size + 16) {
+            Some(seq) => u32::from_le_bytes([seq[0], seq[1], seq[2], seq[3]]),
+            None => 0,
+        };
+
+        Ok(Self {
+            player_uuid,
+            position: Vec3::from_array(position),
+            seq,
+        })
+    }
+}
+
+// The server replies to each player_update with the position it accepted
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct PlayerUpdateAck {
+    pub seq: u32,
+    pub position: Vec3,
 }
 
 #[derive(Clone, Default, Debug)]
@@ -146,6 +225,12 @@ struct RawJoinReplyResponse {
     player: Player,
 }
 
+#[derive(Default, Serialize, Deserialize, Debug)]
+struct RawPlayerUpdateAckReply {
+    status: String,
+    response: PlayerUpdateAck,
+}
+
 #[derive(Default, Serialize, Deserialize, Debug)]
 struct RawRoomsUpdate {
     rooms: Vec<RoomUpdateArray>,
@@ -183,3 +268,12 @@ fn get_players(player_presences: HashMap<String, PlayerPresence>) -> Vec<Player>
     }
     players
 }
+
+fn decode_payload<T: DeserializeOwned>(message: &Message) -> Result<T, DecodeError> {
+    let Some(payload) = message.payload.as_json() else {
+        let error = serde_json::Error::custom("unexpected binary payload");
+        return Err(DecodeError::new(message, error));
+    };
+
+    T::deserialize(payload).map_err(|error| DecodeError::new(message, error))
+}
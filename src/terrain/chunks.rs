@@ -0,0 +1,500 @@
+use super::tile_map::{neighbours, PropKind, Tile, TileKind, TileMap};
+use super::TileAssets;
+use crate::collision::collider::{Collider, Static};
+use crate::player::systems::{FriendTag, PlayerTag};
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task};
+use bevy::utils::{HashMap, HashSet};
+
+/// This module streams the terrain in square chunks of tiles around the local player.
+/// Chunks within `view_radius` of the player's chunk are loaded, nearest first, and chunks
+/// that fall out of range are despawned. Each chunk's ground is drawn as a single mesh, built
+/// on the async compute pool from a copy of its tiles, and `chunks_per_frame` caps how many
+/// chunks are started and finished in a frame. Chunks are rebuilt in place when their tiles
+/// change. Props, the chunk's see-through water, and invisible colliders for the wall columns
+/// are spawned as children of their chunk.
+
+// Tiles are columns reaching down to here, so raised tiles read as solid blocks
+const TILE_BOTTOM_Y: f32 = -0.3;
+// Thinnest a column is drawn, for tiles at or below TILE_BOTTOM_Y
+const MIN_TILE_THICKNESS: f32 = 0.05;
+
+#[derive(Clone, Debug, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct Streaming {
+    // Width and depth of a chunk in tiles
+    pub chunk_size: u32,
+    // Chunks this many chunks away from the player's chunk are kept loaded
+    pub view_radius: u32,
+    // Most chunks started, and most chunks finished, in a single frame
+    pub chunks_per_frame: usize,
+}
+
+impl Default for Streaming {
+    fn default() -> Self {
+        Self {
+            chunk_size: 8,
+            view_radius: 2,
+            chunks_per_frame: 2,
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct TerrainChunk {
+    pub coords: UVec2,
+}
+
+// Meshes of a chunk being built in the background
+#[derive(Component)]
+pub struct ChunkTask(Task<ChunkMeshes>);
+
+// Water is kept out of the ground mesh so it can be drawn with a blended material
+pub struct ChunkMeshes {
+    ground: Mesh,
+    // None when the chunk has no water
+    water: Option<Mesh>,
+}
+
+#[derive(Component, Debug)]
+pub struct TerrainWater {
+    pub coords: UVec2,
+}
+
+#[derive(Component, Debug)]
+pub struct TerrainProp {
+    pub coords: UVec2,
+}
+
+// Invisible collider filling the column of a wall tile
+#[derive(Component, Debug)]
+pub struct TerrainWall {
+    pub coords: UVec2,
+}
+
+// Sent after a tile of the TileMap is changed, so the chunks showing it are rebuilt
+#[derive(Event, Debug)]
+pub struct TileUpdateEvent {
+    pub coords: UVec2,
+}
+
+impl TileUpdateEvent {
+    pub fn new(coords: UVec2) -> Self {
+        Self { coords }
+    }
+}
+
+// Chunk entities by chunk coords, both loaded and still building
+#[derive(Resource, Debug, Default)]
+pub struct LoadedChunks {
+    chunks: HashMap<UVec2, Entity>,
+}
+
+impl LoadedChunks {
+    pub fn get(&self, coords: UVec2) -> Option<Entity> {
+        self.chunks.get(&coords).copied()
+    }
+}
+
+// Copy of a chunk's tiles with a one tile border, so the mesh can be built off the main thread
+struct ChunkData {
+    tile_size: f32,
+    // Chunk size plus the border
+    width: u32,
+    depth: u32,
+    // Top center of each tile, None outside the map
+    tiles: Vec<Option<(Vec3, Tile)>>,
+}
+
+impl ChunkData {
+    fn new(tile_map: &TileMap, coords: UVec2, chunk_size: u32) -> Self {
+        let first = (coords * chunk_size).as_ivec2() - IVec2::ONE;
+        let width = chunk_size.min(tile_map.width - coords.x * chunk_size) + 2;
+        let depth = chunk_size.min(tile_map.depth - coords.y * chunk_size) + 2;
+
+        let tiles = (0..depth as i32)
+            .flat_map(|z| (0..width as i32).map(move |x| first + IVec2::new(x, z)))
+            .map(|tile_coords| {
+                if tile_coords.min_element() < 0 {
+                    return None;
+                }
+                let tile_coords = tile_coords.as_uvec2();
+                let tile = tile_map.get(tile_coords)?;
+                Some((tile_map.tile_center(tile_coords), *tile))
+            })
+            .collect();
+
+        Self {
+            tile_size: tile_map.tile_size,
+            width,
+            depth,
+            tiles,
+        }
+    }
+
+    fn get(&self, x: i32, z: i32) -> Option<(Vec3, Tile)> {
+        if x < 0 || z < 0 || x >= self.width as i32 || z >= self.depth as i32 {
+            return None;
+        }
+        self.tiles[(z * self.width as i32 + x) as usize]
+    }
+
+    // One column per tile, leaving out the sides hidden by taller neighbours.
+    // Water columns go in their own mesh, over a bed at the bottom of the ground mesh.
+    fn build_meshes(&self) -> ChunkMeshes {
+        let mut ground = MeshBuilder::default();
+        let mut water = MeshBuilder::default();
+        let half_tile = self.tile_size / 2.0;
+
+        for z in 1..self.depth as i32 - 1 {
+            for x in 1..self.width as i32 - 1 {
+                let Some((top, tile)) = self.get(x, z) else {
+                    continue;
+                };
+                let bottom = top.y - (top.y - TILE_BOTTOM_Y).max(MIN_TILE_THICKNESS);
+                let is_water = tile.kind == TileKind::Water;
+
+                // Water takes its color from its material
+                let (builder, color) = if is_water {
+                    let bed = Vec3::new(top.x, bottom, top.z);
+                    let bed_color = tile_color(tile.kind).as_linear_rgba_f32();
+                    ground.push_quad(bed, Vec3::Z * half_tile, Vec3::X * half_tile, bed_color);
+                    (&mut water, Color::WHITE.as_linear_rgba_f32())
+                } else {
+                    (&mut ground, tile_color(tile.kind).as_linear_rgba_f32())
+                };
+
+                builder.push_quad(top, Vec3::Z * half_tile, Vec3::X * half_tile, color);
+
+                for (dx, dz) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                    let normal = Vec3::new(dx as f32, 0.0, dz as f32);
+                    // Ground sides reach down to the bed under neighbouring water
+                    let from = match self.get(x + dx, z + dz) {
+                        Some((neighbour, other)) if is_water || other.kind != TileKind::Water => {
+                            neighbour.y.max(bottom)
+                        }
+                        _ => bottom,
+                    };
+                    if from >= top.y {
+                        continue;
+                    }
+
+                    let half_height = (top.y - from) / 2.0;
+                    let center = Vec3::new(top.x, from + half_height, top.z) + normal * half_tile;
+                    builder.push_quad(
+                        center,
+                        Vec3::Y.cross(normal) * half_tile,
+                        Vec3::Y * half_height,
+                        color,
+                    );
+                }
+            }
+        }
+
+        ChunkMeshes {
+            ground: ground.build(),
+            water: (!water.is_empty()).then(|| water.build()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct MeshBuilder {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+
+impl MeshBuilder {
+    // Quad around center spanning ±u and ±v, facing u × v
+    fn push_quad(&mut self, center: Vec3, u: Vec3, v: Vec3, color: [f32; 4]) {
+        let start = self.positions.len() as u32;
+        let normal = u.cross(v).normalize_or_zero();
+
+        for corner in [
+            center - u - v,
+            center + u - v,
+            center + u + v,
+            center - u + v,
+        ] {
+            self.positions.push(corner.into());
+            self.normals.push(normal.into());
+            self.colors.push(color);
+        }
+        self.indices
+            .extend([start, start + 1, start + 2, start, start + 2, start + 3]);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    fn build(self) -> Mesh {
+        Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::RENDER_WORLD,
+        )
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, self.positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_COLOR, self.colors)
+        .with_inserted_indices(Indices::U32(self.indices))
+    }
+}
+
+pub fn tile_color(kind: TileKind) -> Color {
+    match kind {
+        TileKind::Floor => Color::rgb(0.3, 0.5, 0.3),
+        TileKind::Wall => Color::rgb(0.45, 0.45, 0.5),
+        TileKind::Water => Color::rgba(0.2, 0.4, 0.8, 0.8),
+        TileKind::Stairs => Color::rgb(0.55, 0.45, 0.3),
+    }
+}
+
+// Chunk containing a tile
+pub fn chunk_coords(tile_coords: UVec2, chunk_size: u32) -> UVec2 {
+    tile_coords / chunk_size.max(1)
+}
+
+// Number of chunks along each axis, the last ones may be partial
+fn chunk_count(tile_map: &TileMap, chunk_size: u32) -> UVec2 {
+    let map_size = UVec2::new(tile_map.width, tile_map.depth);
+    (map_size + chunk_size - 1) / chunk_size
+}
+
+fn chunk_distance(a: UVec2, b: UVec2) -> u32 {
+    let difference = (a.as_ivec2() - b.as_ivec2()).abs();
+    difference.x.max(difference.y) as u32
+}
+
+// Start loading chunks that came into view, and despawn chunks that left it
+pub fn stream_chunks(
+    mut commands: Commands,
+    mut loaded: ResMut<LoadedChunks>,
+    player_query: Query<&Transform, (With<PlayerTag>, Without<FriendTag>)>,
+    tile_map: Res<TileMap>,
+    streaming: Res<Streaming>,
+) {
+    let chunk_size = streaming.chunk_size.max(1);
+    let player_position = player_query
+        .get_single()
+        .map(|transform| transform.translation)
+        .unwrap_or_default();
+    let Some(player_tile) = tile_map.tile_coords(tile_map.clamp(player_position, 0.0)) else {
+        return;
+    };
+    let center = chunk_coords(player_tile, chunk_size);
+    let radius = streaming.view_radius;
+
+    // One chunk of slack, so walking back and forth over a border doesn't reload chunks
+    loaded.chunks.retain(|&coords, &mut entity| {
+        let in_view = chunk_distance(coords, center) <= radius + 1;
+        if !in_view {
+            commands.entity(entity).despawn_recursive();
+        }
+        in_view
+    });
+
+    let count = chunk_count(&tile_map, chunk_size);
+    let min = center.saturating_sub(UVec2::splat(radius));
+    let max = (center + radius).min(count - 1);
+    let mut missing: Vec<UVec2> = (min.y..=max.y)
+        .flat_map(|z| (min.x..=max.x).map(move |x| UVec2::new(x, z)))
+        .filter(|coords| !loaded.chunks.contains_key(coords))
+        .collect();
+    missing.sort_by_key(|&coords| chunk_distance(coords, center));
+
+    for coords in missing.into_iter().take(streaming.chunks_per_frame) {
+        let entity = commands
+            .spawn((
+                SpatialBundle::default(),
+                TerrainChunk { coords },
+                build_chunk(&tile_map, coords, chunk_size),
+                Name::new(format!("Chunk {},{}", coords.x, coords.y)),
+            ))
+            .id();
+        loaded.chunks.insert(coords, entity);
+    }
+}
+
+// Rebuild loaded chunks whose tiles changed, keeping the old mesh until the new one is ready
+pub fn rebuild_updated_chunks(
+    mut commands: Commands,
+    mut update_event_reader: EventReader<TileUpdateEvent>,
+    loaded: Res<LoadedChunks>,
+    tile_map: Res<TileMap>,
+    streaming: Res<Streaming>,
+) {
+    let chunk_size = streaming.chunk_size.max(1);
+    let mut updated = HashSet::new();
+
+    for &TileUpdateEvent { coords } in update_event_reader.read() {
+        // Neighbouring chunks too, their side faces depend on the heights along the border
+        updated.insert(chunk_coords(coords, chunk_size));
+        for neighbour in neighbours(coords) {
+            updated.insert(chunk_coords(neighbour, chunk_size));
+        }
+    }
+
+    for coords in updated {
+        if let Some(entity) = loaded.get(coords) {
+            commands
+                .entity(entity)
+                .insert(build_chunk(&tile_map, coords, chunk_size));
+        }
+    }
+}
+
+// Start building a chunk's meshes in the background
+fn build_chunk(tile_map: &TileMap, coords: UVec2, chunk_size: u32) -> ChunkTask {
+    let data = ChunkData::new(tile_map, coords, chunk_size);
+    ChunkTask(AsyncComputeTaskPool::get().spawn(async move { data.build_meshes() }))
+}
+
+// Add the meshes of chunks that finished building, along with their water and props
+pub fn finish_chunks(
+    mut commands: Commands,
+    mut task_query: Query<(Entity, &TerrainChunk, &mut ChunkTask)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    tile_map: Res<TileMap>,
+    tile_assets: Res<TileAssets>,
+    streaming: Res<Streaming>,
+) {
+    let chunk_size = streaming.chunk_size.max(1);
+    let mut finished = 0;
+
+    for (entity, chunk, mut task) in task_query.iter_mut() {
+        if finished >= streaming.chunks_per_frame {
+            break;
+        }
+        let Some(chunk_meshes) = block_on(future::poll_once(&mut task.0)) else {
+            continue;
+        };
+        finished += 1;
+
+        commands
+            .entity(entity)
+            .remove::<ChunkTask>()
+            .insert((
+                meshes.add(chunk_meshes.ground),
+                tile_assets.material.clone(),
+            ))
+            .despawn_descendants()
+            .with_children(|parent| {
+                if let Some(water) = chunk_meshes.water {
+                    parent.spawn((
+                        PbrBundle {
+                            mesh: meshes.add(water),
+                            material: tile_assets.water_material.clone(),
+                            ..default()
+                        },
+                        TerrainWater {
+                            coords: chunk.coords,
+                        },
+                        Name::new("Water"),
+                    ));
+                }
+                for (coords, kind) in chunk_props(&tile_map, chunk.coords, chunk_size) {
+                    parent.spawn(prop_bundle(&tile_map, &tile_assets, coords, kind));
+                }
+                for coords in chunk_walls(&tile_map, chunk.coords, chunk_size) {
+                    parent.spawn(wall_bundle(&tile_map, coords));
+                }
+            });
+    }
+}
+
+fn chunk_tiles(chunk: UVec2, chunk_size: u32) -> impl Iterator<Item = UVec2> {
+    let first = chunk * chunk_size;
+    (first.y..first.y + chunk_size)
+        .flat_map(move |z| (first.x..first.x + chunk_size).map(move |x| UVec2::new(x, z)))
+}
+
+fn chunk_props(
+    tile_map: &TileMap,
+    chunk: UVec2,
+    chunk_size: u32,
+) -> impl Iterator<Item = (UVec2, PropKind)> + '_ {
+    chunk_tiles(chunk, chunk_size).filter_map(|coords| Some((coords, tile_map.get(coords)?.prop?)))
+}
+
+fn chunk_walls(
+    tile_map: &TileMap,
+    chunk: UVec2,
+    chunk_size: u32,
+) -> impl Iterator<Item = UVec2> + '_ {
+    chunk_tiles(chunk, chunk_size).filter(|&coords| {
+        tile_map
+            .get(coords)
+            .is_some_and(|tile| tile.kind == TileKind::Wall)
+    })
+}
+
+// Props stand on top of their tile, a little smaller than it
+pub fn prop_bundle(
+    tile_map: &TileMap,
+    tile_assets: &TileAssets,
+    coords: UVec2,
+    kind: PropKind,
+) -> (PbrBundle, TerrainProp, Collider, Static, Name) {
+    let size = prop_size(kind) * tile_map.tile_size;
+
+    (
+        PbrBundle {
+            mesh: tile_assets.prop_meshes[&kind].clone(),
+            material: tile_assets.prop_materials[&kind].clone(),
+            transform: Transform {
+                translation: tile_map.tile_center(coords) + Vec3::Y * size.y / 2.0,
+                scale: size,
+                ..default()
+            },
+            ..default()
+        },
+        TerrainProp { coords },
+        prop_collider(kind),
+        Static,
+        Name::new(format!("{kind:?} {},{}", coords.x, coords.y)),
+    )
+}
+
+// Shapes fitting the unit prop meshes before they are scaled to prop_size
+fn prop_collider(kind: PropKind) -> Collider {
+    match kind {
+        PropKind::Rock => Collider::Sphere { radius: 0.5 },
+        PropKind::Tree => Collider::Capsule {
+            radius: 0.5,
+            half_height: 0.0,
+        },
+    }
+}
+
+// Walls block movement through the whole column, from the bottom of the terrain to the top
+fn wall_bundle(
+    tile_map: &TileMap,
+    coords: UVec2,
+) -> (TransformBundle, TerrainWall, Collider, Static, Name) {
+    let center = tile_map.tile_center(coords);
+    let height = (center.y - TILE_BOTTOM_Y).max(MIN_TILE_THICKNESS);
+
+    (
+        TransformBundle::from_transform(Transform {
+            translation: Vec3::new(center.x, center.y - height / 2.0, center.z),
+            scale: Vec3::new(tile_map.tile_size, height, tile_map.tile_size),
+            ..default()
+        }),
+        TerrainWall { coords },
+        Collider::cuboid(Vec3::ONE),
+        Static,
+        Name::new(format!("Wall {},{}", coords.x, coords.y)),
+    )
+}
+
+// Size of a prop relative to the tile size
+pub fn prop_size(kind: PropKind) -> Vec3 {
+    match kind {
+        PropKind::Rock => Vec3::new(0.7, 0.4, 0.7),
+        PropKind::Tree => Vec3::new(0.35, 1.6, 0.35),
+    }
+}
@@ -0,0 +1,304 @@
+use super::tile_map::{neighbours, PropKind, Tile, TileKind, TileMap, MAX_STEP_HEIGHT};
+use bevy::math::{UVec2, Vec2};
+
+/// This module contains the procedural terrain generator.
+/// Heights come from fractal value noise, pushed down towards the edges of the map so the
+/// land forms an island, then cut into water, floor terraces and rocky walls. Props are
+/// scattered over the floor, and stairs are placed wherever a terrace is too high to step onto.
+/// Everything is derived from hashing the seed and room name, without `rand`, so every client
+/// in a room builds an identical map on any platform.
+
+const WATER_HEIGHT: f32 = -0.1;
+// How far walls rise above the terrace they stand on
+const WALL_RISE: f32 = 0.3;
+// Salts, so props don't line up with features of the heightmap
+const NOISE_SALT: u64 = 0x006e_6f69_7365;
+const PROP_SALT: u64 = 0x7072_6f70;
+
+#[derive(Clone, Debug)]
+pub struct Generator {
+    pub seed: u64,
+    pub width: u32,
+    pub depth: u32,
+    pub tile_size: f32,
+    // Size of the largest noise features, in tiles
+    pub noise_scale: f32,
+    // Layers of noise, each at twice the frequency of the last
+    pub octaves: u32,
+    // How much each octave contributes relative to the last
+    pub persistence: f32,
+    // How strongly the land drops off towards the edges, 0 = no island
+    pub island_falloff: f32,
+    // Noise values (0..1) below this are water, above mountain_level are walls
+    pub water_level: f32,
+    pub mountain_level: f32,
+    // Height of the highest floor terrace, with terraces max_height / height_step apart
+    pub max_height: f32,
+    pub height_step: f32,
+    // Chance of a prop on each floor tile
+    pub prop_density: f32,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            width: 16,
+            depth: 16,
+            tile_size: 0.5,
+            noise_scale: 6.0,
+            octaves: 3,
+            persistence: 0.5,
+            island_falloff: 0.4,
+            water_level: 0.3,
+            mountain_level: 0.7,
+            max_height: 0.4,
+            height_step: 0.1,
+            prop_density: 0.06,
+        }
+    }
+}
+
+impl Generator {
+    // Generate the map for a room, the same for every client using the same parameters
+    pub fn generate(&self, room: &str) -> TileMap {
+        let seed = room_seed(self.seed, room);
+
+        let mut tiles = Vec::with_capacity((self.width * self.depth) as usize);
+        for z in 0..self.depth {
+            for x in 0..self.width {
+                tiles.push(self.tile(seed, UVec2::new(x, z)));
+            }
+        }
+
+        let mut tile_map = TileMap::new(self.width, self.depth, self.tile_size, tiles);
+        place_stairs(&mut tile_map);
+        tile_map
+    }
+
+    fn tile(&self, seed: u64, coords: UVec2) -> Tile {
+        let value = self.island_value(seed, coords);
+        if value < self.water_level {
+            return Tile::new(TileKind::Water, WATER_HEIGHT);
+        }
+
+        // Terraces between the water and mountain levels
+        let range = (self.mountain_level - self.water_level).max(f32::EPSILON);
+        let land = ((value - self.water_level) / range).clamp(0.0, 1.0);
+        let height = quantize(land * self.max_height, self.height_step);
+        if value >= self.mountain_level {
+            return Tile::new(TileKind::Wall, height + WALL_RISE);
+        }
+
+        let tile = Tile::new(TileKind::Floor, height);
+        let roll = unit(hash(seed ^ PROP_SALT, coords.x as i64, coords.y as i64));
+        if roll >= self.prop_density {
+            return tile;
+        }
+
+        // Reuse the roll to pick a kind, it is uniform within 0..prop_density
+        let index = (roll / self.prop_density * PropKind::ALL.len() as f32) as usize;
+        tile.with_prop(PropKind::ALL[index.min(PropKind::ALL.len() - 1)])
+    }
+
+    // Noise value in 0..1, lowered towards the edges of the map
+    fn island_value(&self, seed: u64, coords: UVec2) -> f32 {
+        let x = coords.x as f32 / self.noise_scale.max(f32::EPSILON);
+        let z = coords.y as f32 / self.noise_scale.max(f32::EPSILON);
+        let noise = fractal_noise(seed ^ NOISE_SALT, x, z, self.octaves, self.persistence);
+
+        // Distance from the center, 0 at the center and 1 at the middle of each edge
+        let half_size = Vec2::new(self.width as f32, self.depth as f32) / 2.0;
+        let distance = ((coords.as_vec2() + 0.5 - half_size) / half_size).length();
+
+        noise - self.island_falloff * distance * distance
+    }
+}
+
+// Turn floor tiles at the foot of a ledge too high to step onto into stairs
+fn place_stairs(tile_map: &mut TileMap) {
+    let needs_stairs: Vec<UVec2> = tile_map
+        .iter()
+        .filter(|(_, tile)| tile.kind == TileKind::Floor && tile.prop.is_none())
+        .filter(|(coords, tile)| {
+            neighbours(*coords).into_iter().any(|neighbour| {
+                tile_map.get(neighbour).is_some_and(|other| {
+                    other.kind == TileKind::Floor && other.height - tile.height > MAX_STEP_HEIGHT
+                })
+            })
+        })
+        .map(|(coords, _)| coords)
+        .collect();
+
+    for coords in needs_stairs {
+        if let Some(&tile) = tile_map.get(coords) {
+            tile_map.set(coords, Tile::new(TileKind::Stairs, tile.height));
+        }
+    }
+}
+
+fn quantize(value: f32, step: f32) -> f32 {
+    if step <= 0.0 {
+        return value;
+    }
+    (value / step).round() * step
+}
+
+// Combine the seed with the room name, using FNV-1a since std's hashers aren't stable
+pub fn room_seed(seed: u64, room: &str) -> u64 {
+    let hash = room.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    mix(seed ^ hash)
+}
+
+// SplitMix64 finalizer, spreads every input bit over the output
+fn mix(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
+
+fn hash(seed: u64, x: i64, z: i64) -> u64 {
+    mix(seed ^ mix(x as u64 ^ mix(z as u64)))
+}
+
+// Map a hash to 0..1
+fn unit(hash: u64) -> f32 {
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+// Value noise: random values at integer points, smoothly interpolated between them
+fn value_noise(seed: u64, x: f32, z: f32) -> f32 {
+    let (x0, z0) = (x.floor(), z.floor());
+    let (tx, tz) = (smoothstep(x - x0), smoothstep(z - z0));
+    let (x0, z0) = (x0 as i64, z0 as i64);
+
+    let corner = |dx: i64, dz: i64| unit(hash(seed, x0 + dx, z0 + dz));
+    let top = lerp(corner(0, 0), corner(1, 0), tx);
+    let bottom = lerp(corner(0, 1), corner(1, 1), tx);
+    lerp(top, bottom, tz)
+}
+
+// Sum of octaves of value noise, normalized back to 0..1
+fn fractal_noise(seed: u64, x: f32, z: f32, octaves: u32, persistence: f32) -> f32 {
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    let mut max_total = 0.0;
+
+    for octave in 0..octaves.max(1) {
+        total += value_noise(
+            seed.wrapping_add(octave as u64),
+            x * frequency,
+            z * frequency,
+        ) * amplitude;
+        max_total += amplitude;
+        amplitude *= persistence;
+        frequency *= 2.0;
+    }
+
+    total / max_total
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::prelude::default;
+    use bevy::utils::HashSet;
+
+    fn tiles(tile_map: &TileMap) -> Vec<Tile> {
+        tile_map.iter().map(|(_, tile)| *tile).collect()
+    }
+
+    // Tiles reachable from start, moving to neighbours the given rule allows
+    fn reachable(
+        tile_map: &TileMap,
+        start: UVec2,
+        can_move: impl Fn(UVec2, UVec2) -> bool,
+    ) -> HashSet<UVec2> {
+        let mut reached = HashSet::from([start]);
+        let mut frontier = vec![start];
+        while let Some(coords) = frontier.pop() {
+            for neighbour in neighbours(coords) {
+                if tile_map.get(neighbour).is_some()
+                    && !reached.contains(&neighbour)
+                    && can_move(coords, neighbour)
+                {
+                    reached.insert(neighbour);
+                    frontier.push(neighbour);
+                }
+            }
+        }
+        reached
+    }
+
+    #[test]
+    fn same_seed_and_room_give_the_same_map() {
+        let generator = Generator {
+            seed: 42,
+            ..default()
+        };
+
+        assert_eq!(
+            tiles(&generator.generate("iso")),
+            tiles(&generator.generate("iso"))
+        );
+    }
+
+    #[test]
+    fn different_seeds_give_different_maps() {
+        let map = |seed| Generator { seed, ..default() }.generate("iso");
+
+        assert_ne!(tiles(&map(1)), tiles(&map(2)));
+        assert_ne!(
+            tiles(&map(1)),
+            tiles(
+                &Generator {
+                    seed: 1,
+                    ..default()
+                }
+                .generate("other")
+            )
+        );
+    }
+
+    #[test]
+    fn place_stairs_keeps_every_floor_reachable() {
+        let mut placed_stairs = false;
+        for seed in 0..20 {
+            // Terraces further apart than a step, so climbing between them needs stairs
+            let tile_map = Generator {
+                seed,
+                width: 24,
+                depth: 24,
+                height_step: 0.3,
+                max_height: 0.9,
+                ..default()
+            }
+            .generate("iso");
+            placed_stairs |= tile_map
+                .iter()
+                .any(|(_, tile)| tile.kind == TileKind::Stairs);
+
+            let is_walkable = |coords| tile_map.get(coords).is_some_and(Tile::is_walkable);
+            for start in tile_map.walkable_tiles() {
+                let connected = reachable(&tile_map, start, |_, to| is_walkable(to));
+                let walked = reachable(&tile_map, start, |from, to| {
+                    tile_map.can_step(tile_map.tile_center(from), tile_map.tile_center(to))
+                });
+                assert_eq!(walked, connected, "seed {seed}, from {start}");
+            }
+        }
+
+        assert!(placed_stairs);
+    }
+}
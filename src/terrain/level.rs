@@ -0,0 +1,188 @@
+use super::tile_map::{PropKind, Tile, TileKind, TileMap};
+use bevy::asset::io::file::FileAssetReader;
+use bevy::math::UVec2;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// This module contains the level file format, a versioned JSON description of a `TileMap`.
+///   - `tiles` has one string per row, with one character per tile kind (see `TileKind::symbol`)
+///   - `heights` has one array per row, with the height of the top of each tile
+///   - `props` is optional, listing the props standing on tiles
+
+pub const LEVEL_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Level {
+    pub version: u32,
+    pub tile_size: f32,
+    pub tiles: Vec<String>,
+    pub heights: Vec<Vec<f32>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub props: Vec<LevelProp>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LevelProp {
+    pub row: usize,
+    pub column: usize,
+    pub kind: PropKind,
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+    InvalidSize(String),
+    InvalidTile {
+        row: usize,
+        column: usize,
+        symbol: char,
+    },
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Json(e) => write!(f, "{e}"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported level version {version}, expected {LEVEL_VERSION}"
+            ),
+            Self::InvalidSize(reason) => write!(f, "invalid level size: {reason}"),
+            Self::InvalidTile {
+                row,
+                column,
+                symbol,
+            } => write!(f, "invalid tile {symbol:?} at row {row}, column {column}"),
+        }
+    }
+}
+
+impl std::error::Error for LevelError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Level {
+    pub fn read(path: &Path) -> Result<Self, LevelError> {
+        let json = fs::read_to_string(path).map_err(LevelError::Io)?;
+        let level: Self = serde_json::from_str(&json).map_err(LevelError::Json)?;
+
+        if level.version != LEVEL_VERSION {
+            return Err(LevelError::UnsupportedVersion(level.version));
+        }
+
+        Ok(level)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), LevelError> {
+        let json = serde_json::to_string_pretty(self).map_err(LevelError::Json)?;
+        fs::write(path, json).map_err(LevelError::Io)
+    }
+
+    pub fn to_tile_map(&self) -> Result<TileMap, LevelError> {
+        let depth = self.tiles.len();
+        let width = self
+            .tiles
+            .first()
+            .map(|row| row.chars().count())
+            .unwrap_or(0);
+        if width == 0 || depth == 0 {
+            return Err(LevelError::InvalidSize("level has no tiles".to_string()));
+        }
+        if self.tile_size.is_nan() || self.tile_size <= 0.0 {
+            return Err(LevelError::InvalidSize(format!(
+                "tile_size must be positive, got {}",
+                self.tile_size
+            )));
+        }
+        if self.heights.len() != depth {
+            return Err(LevelError::InvalidSize(format!(
+                "{depth} rows of tiles but {} rows of heights",
+                self.heights.len()
+            )));
+        }
+
+        let mut tiles = Vec::with_capacity(width * depth);
+        for (row, (symbols, heights)) in self.tiles.iter().zip(&self.heights).enumerate() {
+            if symbols.chars().count() != width || heights.len() != width {
+                return Err(LevelError::InvalidSize(format!(
+                    "row {row} is not {width} tiles wide"
+                )));
+            }
+
+            for (column, (symbol, height)) in symbols.chars().zip(heights).enumerate() {
+                let kind = TileKind::from_symbol(symbol).ok_or(LevelError::InvalidTile {
+                    row,
+                    column,
+                    symbol,
+                })?;
+                tiles.push(Tile::new(kind, *height));
+            }
+        }
+
+        for prop in &self.props {
+            if prop.row >= depth || prop.column >= width {
+                return Err(LevelError::InvalidSize(format!(
+                    "prop at row {}, column {} is outside the level",
+                    prop.row, prop.column
+                )));
+            }
+            let tile = &mut tiles[prop.row * width + prop.column];
+            *tile = tile.with_prop(prop.kind);
+        }
+
+        Ok(TileMap::new(
+            width as u32,
+            depth as u32,
+            self.tile_size,
+            tiles,
+        ))
+    }
+
+    pub fn from_tile_map(tile_map: &TileMap) -> Self {
+        let rows: Vec<Vec<Tile>> = (0..tile_map.depth)
+            .map(|z| {
+                (0..tile_map.width)
+                    .filter_map(|x| tile_map.get(UVec2::new(x, z)).copied())
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            version: LEVEL_VERSION,
+            tile_size: tile_map.tile_size,
+            tiles: rows
+                .iter()
+                .map(|row| row.iter().map(|tile| tile.kind.symbol()).collect())
+                .collect(),
+            heights: rows
+                .iter()
+                .map(|row| row.iter().map(|tile| tile.height).collect())
+                .collect(),
+            props: rows
+                .iter()
+                .enumerate()
+                .flat_map(|(row, tiles)| {
+                    tiles.iter().enumerate().filter_map(move |(column, tile)| {
+                        tile.prop.map(|kind| LevelProp { row, column, kind })
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+// Path of a level file relative to the assets directory, e.g. "levels/default.json"
+pub fn level_path(level: &str) -> PathBuf {
+    FileAssetReader::get_base_path().join("assets").join(level)
+}
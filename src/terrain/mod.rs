@@ -0,0 +1,185 @@
+pub mod chunks;
+pub mod generator;
+pub mod level;
+pub mod pathfinding;
+pub mod tile_map;
+
+use self::chunks::{
+    finish_chunks, rebuild_updated_chunks, stream_chunks, tile_color, LoadedChunks, Streaming,
+    TileUpdateEvent,
+};
+use self::generator::Generator;
+use self::level::{level_path, Level};
+use self::tile_map::{PropKind, TileKind, TileMap};
+use crate::schedule::{PreStartupSet, UpdateSet};
+use crate::socket::GAME_ROOM;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+pub const DEFAULT_LEVEL: &str = "levels/default.json";
+
+// Shared materials for chunks, and meshes for props so all props of a kind are drawn as instances
+#[derive(Resource, Debug)]
+pub struct TileAssets {
+    // Chunk meshes carry the tile colors as vertex colors
+    pub material: Handle<StandardMaterial>,
+    // Water is see-through, so it has its own mesh and color
+    pub water_material: Handle<StandardMaterial>,
+    pub prop_meshes: HashMap<PropKind, Handle<Mesh>>,
+    pub prop_materials: HashMap<PropKind, Handle<StandardMaterial>>,
+}
+
+#[derive(Clone, Debug)]
+pub enum TerrainSource {
+    // Level file, relative to the assets directory
+    Level(String),
+    Generated(Generator),
+}
+
+impl TerrainSource {
+    // Build the map for a room, generated maps differ between rooms while levels don't
+    pub fn build(&self, room: &str) -> TileMap {
+        self.load(room).0
+    }
+
+    // Build the map for a room, along with the level file it came from
+    pub fn load(&self, room: &str) -> (TileMap, LoadedLevel) {
+        match self {
+            Self::Level(level) => match read_tile_map(level) {
+                Some(tile_map) => (
+                    tile_map,
+                    LoadedLevel {
+                        level: Some(level.clone()),
+                    },
+                ),
+                None => (TileMap::default(), LoadedLevel::default()),
+            },
+            Self::Generated(generator) => (generator.generate(room), LoadedLevel::default()),
+        }
+    }
+}
+
+// Level file the TileMap was loaded from, None if it was generated or fell back to a flat map
+#[derive(Resource, Debug, Default)]
+pub struct LoadedLevel {
+    pub level: Option<String>,
+}
+
+impl Default for TerrainSource {
+    fn default() -> Self {
+        Self::Level(DEFAULT_LEVEL.to_string())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub source: TerrainSource,
+    // Room the terrain is generated for, clients in the same room build the same map
+    pub room: String,
+    pub streaming: Streaming,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            source: TerrainSource::default(),
+            room: GAME_ROOM.to_string(),
+            streaming: Streaming::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TerrainPlugin {
+    pub config: Config,
+}
+
+impl Default for TerrainPlugin {
+    fn default() -> Self {
+        Self {
+            config: Config::default(),
+        }
+    }
+}
+
+impl Plugin for TerrainPlugin {
+    fn build(&self, app: &mut App) {
+        let (tile_map, loaded_level) = self.config.source.load(&self.config.room);
+
+        app.insert_resource(tile_map)
+            .insert_resource(loaded_level)
+            .insert_resource(self.config.streaming.clone())
+            .init_resource::<LoadedChunks>()
+            .add_event::<TileUpdateEvent>()
+            .register_type::<TileMap>()
+            .register_type::<Streaming>()
+            .add_systems(
+                PreStartup,
+                create_tile_assets.in_set(PreStartupSet::SpawnWorld),
+            )
+            .add_systems(
+                Update,
+                (rebuild_updated_chunks, stream_chunks, finish_chunks)
+                    .chain()
+                    .in_set(UpdateSet::AfterEffects),
+            );
+    }
+}
+
+// Load a level into a TileMap, or None (logged) if the level can't be loaded
+fn read_tile_map(level: &str) -> Option<TileMap> {
+    match Level::read(&level_path(level)).and_then(|level| level.to_tile_map()) {
+        Ok(tile_map) => {
+            info!(
+                "loaded level {level} ({}x{})",
+                tile_map.width, tile_map.depth
+            );
+            Some(tile_map)
+        }
+        Err(e) => {
+            error!("failed to load level {level}: {e}, using a flat map");
+            None
+        }
+    }
+}
+
+fn prop_color(kind: PropKind) -> Color {
+    match kind {
+        PropKind::Rock => Color::rgb(0.5, 0.5, 0.48),
+        PropKind::Tree => Color::rgb(0.15, 0.35, 0.15),
+    }
+}
+
+fn create_tile_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let material = materials.add(StandardMaterial {
+        reflectance: 0.01,
+        ..default()
+    });
+    let water_material = materials.add(StandardMaterial {
+        base_color: tile_color(TileKind::Water),
+        reflectance: 0.3,
+        alpha_mode: AlphaMode::Blend,
+        ..default()
+    });
+    let prop_materials = PropKind::ALL
+        .into_iter()
+        .map(|kind| (kind, materials.add(prop_color(kind))))
+        .collect();
+    let prop_meshes = [
+        (PropKind::Rock, meshes.add(Sphere::new(0.5))),
+        (PropKind::Tree, meshes.add(Cylinder::new(0.5, 1.0))),
+    ]
+    .into_iter()
+    .collect();
+
+    commands.insert_resource(TileAssets {
+        material,
+        water_material,
+        prop_meshes,
+        prop_materials,
+    });
+}
@@ -0,0 +1,250 @@
+use super::tile_map::TileMap;
+use bevy::math::{IVec2, UVec2};
+use bevy::utils::HashMap;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// This module contains A* pathfinding over the walkable tiles of a `TileMap`.
+/// Paths move between tile centers in eight directions, following the same rules as movement:
+/// walls, water and props block a tile, and ledges can only be climbed by stairs.
+/// Diagonal moves are only taken when both tiles beside them can be walked too, so paths
+/// never cut the corner of an obstacle.
+
+// Costs of straight and diagonal moves, scaled so diagonals are about √2 times longer
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+// Most tiles expanded before giving up, so an unreachable goal on a big map can't stall a frame
+const MAX_EXPANDED_TILES: usize = 10_000;
+
+const DIRECTIONS: [IVec2; 8] = [
+    IVec2::new(1, 0),
+    IVec2::new(-1, 0),
+    IVec2::new(0, 1),
+    IVec2::new(0, -1),
+    IVec2::new(1, 1),
+    IVec2::new(1, -1),
+    IVec2::new(-1, 1),
+    IVec2::new(-1, -1),
+];
+
+// Tiles to walk through to get from one tile to another, excluding `from` and including `to`
+pub fn find_path(tile_map: &TileMap, from: UVec2, to: UVec2) -> Option<Vec<UVec2>> {
+    if !tile_map.get(to)?.is_walkable() {
+        return None;
+    }
+    if from == to {
+        return Some(Vec::new());
+    }
+
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<UVec2, UVec2> = HashMap::new();
+    let mut costs: HashMap<UVec2, u32> = HashMap::new();
+    open.push(Reverse((heuristic(from, to), 0, from.x, from.y)));
+    costs.insert(from, 0);
+
+    let mut expanded = 0;
+    while let Some(Reverse((_, cost, x, y))) = open.pop() {
+        let current = UVec2::new(x, y);
+        if current == to {
+            return Some(reconstruct_path(&came_from, from, to));
+        }
+        // Skip entries superseded by a cheaper route
+        if costs.get(&current).is_some_and(|&best| cost > best) {
+            continue;
+        }
+
+        expanded += 1;
+        if expanded > MAX_EXPANDED_TILES {
+            return None;
+        }
+
+        for direction in DIRECTIONS {
+            let Some((next, step_cost)) = step(tile_map, current, direction) else {
+                continue;
+            };
+
+            let next_cost = cost + step_cost;
+            if costs.get(&next).is_some_and(|&best| next_cost >= best) {
+                continue;
+            }
+            costs.insert(next, next_cost);
+            came_from.insert(next, current);
+            open.push(Reverse((
+                next_cost + heuristic(next, to),
+                next_cost,
+                next.x,
+                next.y,
+            )));
+        }
+    }
+
+    None
+}
+
+// Tile reached by moving one tile in a direction, if it can be walked to, with the move's cost
+fn step(tile_map: &TileMap, from: UVec2, direction: IVec2) -> Option<(UVec2, u32)> {
+    let can_step = |to: IVec2| {
+        if to.min_element() < 0 {
+            return None;
+        }
+        let to = to.as_uvec2();
+        tile_map
+            .can_step(tile_map.tile_center(from), tile_map.tile_center(to))
+            .then_some(to)
+    };
+
+    let to = can_step(from.as_ivec2() + direction)?;
+    if direction.x == 0 || direction.y == 0 {
+        return Some((to, STRAIGHT_COST));
+    }
+
+    // Both tiles beside a diagonal must be walkable, so it doesn't cut a corner
+    can_step(from.as_ivec2() + IVec2::new(direction.x, 0))?;
+    can_step(from.as_ivec2() + IVec2::new(0, direction.y))?;
+    Some((to, DIAGONAL_COST))
+}
+
+// Octile distance, the cost of the best path if nothing is in the way
+fn heuristic(from: UVec2, to: UVec2) -> u32 {
+    let difference = (from.as_ivec2() - to.as_ivec2()).abs().as_uvec2();
+    let diagonal = difference.min_element();
+    let straight = difference.max_element() - diagonal;
+    straight * STRAIGHT_COST + diagonal * DIAGONAL_COST
+}
+
+fn reconstruct_path(came_from: &HashMap<UVec2, UVec2>, from: UVec2, to: UVec2) -> Vec<UVec2> {
+    let mut path = vec![to];
+    let mut current = to;
+    while let Some(&previous) = came_from.get(&current) {
+        if previous == from {
+            break;
+        }
+        path.push(previous);
+        current = previous;
+    }
+
+    path.reverse();
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::tile_map::{Tile, TileKind};
+
+    // Map drawn with level file symbols, one row per z
+    fn tile_map(rows: &[&str]) -> TileMap {
+        let tiles = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(|symbol| Tile::new(TileKind::from_symbol(symbol).unwrap(), 0.0))
+            .collect();
+        TileMap::new(rows[0].len() as u32, rows.len() as u32, 1.0, tiles)
+    }
+
+    // Rows of floor joined by gaps at alternating ends, so the only path snakes through them all
+    fn serpentine(width: u32, rows: u32) -> TileMap {
+        let wall_row = |gap: u32| {
+            (0..width)
+                .map(|x| if x == gap { '.' } else { '#' })
+                .collect::<String>()
+        };
+        let floor_row = ".".repeat(width as usize);
+
+        let mut map = vec![floor_row.clone()];
+        for row in 1..rows {
+            map.push(wall_row(if row % 2 == 1 { width - 1 } else { 0 }));
+            map.push(floor_row.clone());
+        }
+        tile_map(&map.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+
+    // Every move goes to a neighbouring tile that can be walked
+    fn assert_walkable(tile_map: &TileMap, from: UVec2, path: &[UVec2]) {
+        let mut previous = from;
+        for &coords in path {
+            let difference = (coords.as_ivec2() - previous.as_ivec2()).abs();
+            assert!(difference.max_element() == 1, "{previous} to {coords}");
+            assert!(tile_map.get(coords).unwrap().is_walkable(), "{coords}");
+            previous = coords;
+        }
+    }
+
+    #[test]
+    fn straight_path() {
+        let tile_map = tile_map(&["....."]);
+
+        assert_eq!(
+            find_path(&tile_map, UVec2::new(0, 0), UVec2::new(4, 0)),
+            Some(vec![
+                UVec2::new(1, 0),
+                UVec2::new(2, 0),
+                UVec2::new(3, 0),
+                UVec2::new(4, 0),
+            ])
+        );
+    }
+
+    #[test]
+    fn path_goes_around_a_wall() {
+        let tile_map = tile_map(&[
+            ".....", //
+            "..#..", "..#..", "..#..", ".....",
+        ]);
+        let (from, to) = (UVec2::new(0, 2), UVec2::new(4, 2));
+
+        let path = find_path(&tile_map, from, to).unwrap();
+        assert_walkable(&tile_map, from, &path);
+        assert_eq!(path.last(), Some(&to));
+        // Two diagonals up to the end of the wall, two straight moves past it, two diagonals down
+        assert_eq!(path.len(), 6);
+    }
+
+    #[test]
+    fn diagonal_does_not_cut_a_corner() {
+        let tile_map = tile_map(&[
+            ".#", //
+            "..",
+        ]);
+
+        assert_eq!(
+            find_path(&tile_map, UVec2::new(0, 0), UVec2::new(1, 1)),
+            Some(vec![UVec2::new(0, 1), UVec2::new(1, 1)])
+        );
+    }
+
+    #[test]
+    fn unreachable_goal_has_no_path() {
+        let tile_map = tile_map(&[
+            ".....", //
+            ".~~~.", ".~.~.", ".~~~.", "..#..",
+        ]);
+
+        assert_eq!(find_path(&tile_map, UVec2::ZERO, UVec2::new(2, 2)), None);
+        assert_eq!(find_path(&tile_map, UVec2::ZERO, UVec2::new(2, 4)), None);
+    }
+
+    #[test]
+    fn search_gives_up_after_max_expanded_tiles() {
+        let width = 101;
+        let short = serpentine(width, 10);
+        let long = serpentine(width, MAX_EXPANDED_TILES as u32 / width + 2);
+        let end = |tile_map: &TileMap| UVec2::new(width - 1, tile_map.depth - 1);
+
+        assert!(find_path(&short, UVec2::ZERO, end(&short)).is_some());
+        assert_eq!(find_path(&long, UVec2::ZERO, end(&long)), None);
+    }
+
+    #[test]
+    fn ledge_is_only_climbed_by_stairs() {
+        let mut tile_map = tile_map(&["..."]);
+        tile_map.set(UVec2::new(2, 0), Tile::new(TileKind::Floor, 0.5));
+        assert_eq!(find_path(&tile_map, UVec2::ZERO, UVec2::new(2, 0)), None);
+
+        tile_map.set(UVec2::new(1, 0), Tile::new(TileKind::Stairs, 0.25));
+        assert_eq!(
+            find_path(&tile_map, UVec2::ZERO, UVec2::new(2, 0)),
+            Some(vec![UVec2::new(1, 0), UVec2::new(2, 0)])
+        );
+    }
+}
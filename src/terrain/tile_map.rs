@@ -0,0 +1,324 @@
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// This module contains the `TileMap` resource, a grid of typed tiles with per-tile heights.
+/// The map is centered on the origin, with tile (0, 0) at the -x/-z corner.
+/// Movement, spawning and collision ask the map what is at a world position.
+
+// Highest ledge a player can step up or down, anything higher needs stairs
+pub const MAX_STEP_HEIGHT: f32 = 0.2;
+// Size of the default flat map, the same as the original slab of terrain
+const DEFAULT_WIDTH: u32 = 10;
+const DEFAULT_DEPTH: u32 = 10;
+const DEFAULT_TILE_SIZE: f32 = 0.5;
+// Distance between samples along a ray, in tiles, refined by bisection once a tile is hit
+const RAY_STEP: f32 = 0.25;
+const RAY_REFINE_STEPS: u32 = 8;
+// Keeps clamped positions strictly inside the map, so they always land on a tile
+const EDGE_MARGIN: f32 = 0.001;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TileKind {
+    #[default]
+    Floor,
+    Wall,
+    Water,
+    Stairs,
+}
+
+impl TileKind {
+    pub const ALL: [Self; 4] = [Self::Floor, Self::Wall, Self::Water, Self::Stairs];
+
+    pub fn is_walkable(self) -> bool {
+        matches!(self, Self::Floor | Self::Stairs)
+    }
+
+    // Single character used for this kind in level files
+    pub fn symbol(self) -> char {
+        match self {
+            Self::Floor => '.',
+            Self::Wall => '#',
+            Self::Water => '~',
+            Self::Stairs => '=',
+        }
+    }
+
+    pub fn from_symbol(symbol: char) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.symbol() == symbol)
+    }
+}
+
+// Decoration standing on a tile, blocking it like a wall
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PropKind {
+    Rock,
+    Tree,
+}
+
+impl PropKind {
+    pub const ALL: [Self; 2] = [Self::Rock, Self::Tree];
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Reflect)]
+pub struct Tile {
+    pub kind: TileKind,
+    // Height of the top of the tile
+    pub height: f32,
+    pub prop: Option<PropKind>,
+}
+
+impl Tile {
+    pub fn new(kind: TileKind, height: f32) -> Self {
+        Self {
+            kind,
+            height,
+            prop: None,
+        }
+    }
+
+    pub fn with_prop(self, prop: PropKind) -> Self {
+        Self {
+            prop: Some(prop),
+            ..self
+        }
+    }
+
+    pub fn is_walkable(&self) -> bool {
+        self.kind.is_walkable() && self.prop.is_none()
+    }
+}
+
+#[derive(Clone, Debug, Resource, Reflect)]
+#[reflect(Resource)]
+pub struct TileMap {
+    pub width: u32,
+    pub depth: u32,
+    // Size of a tile in world units
+    pub tile_size: f32,
+    // Row-major, one row of `width` tiles per z
+    tiles: Vec<Tile>,
+}
+
+impl Default for TileMap {
+    fn default() -> Self {
+        Self::flat(DEFAULT_WIDTH, DEFAULT_DEPTH, DEFAULT_TILE_SIZE)
+    }
+}
+
+impl TileMap {
+    pub fn new(width: u32, depth: u32, tile_size: f32, tiles: Vec<Tile>) -> Self {
+        assert_eq!(tiles.len(), (width * depth) as usize, "tile count mismatch");
+        Self {
+            width,
+            depth,
+            tile_size,
+            tiles,
+        }
+    }
+
+    pub fn flat(width: u32, depth: u32, tile_size: f32) -> Self {
+        let tiles = vec![Tile::default(); (width * depth) as usize];
+        Self::new(width, depth, tile_size, tiles)
+    }
+
+    fn index(&self, coords: UVec2) -> Option<usize> {
+        if coords.x < self.width && coords.y < self.depth {
+            Some((coords.y * self.width + coords.x) as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn get(&self, coords: UVec2) -> Option<&Tile> {
+        self.tiles.get(self.index(coords)?)
+    }
+
+    pub fn set(&mut self, coords: UVec2, tile: Tile) {
+        if let Some(index) = self.index(coords) {
+            self.tiles[index] = tile;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (UVec2, &Tile)> {
+        let width = self.width;
+        self.tiles.iter().enumerate().map(move |(index, tile)| {
+            let index = index as u32;
+            (UVec2::new(index % width, index / width), tile)
+        })
+    }
+
+    // Width and depth of the whole map in world units
+    pub fn size(&self) -> Vec2 {
+        Vec2::new(self.width as f32, self.depth as f32) * self.tile_size
+    }
+
+    pub fn tile_coords(&self, position: Vec3) -> Option<UVec2> {
+        let half_size = self.size() / 2.0;
+        let x = ((position.x + half_size.x) / self.tile_size).floor();
+        let z = ((position.z + half_size.y) / self.tile_size).floor();
+        if x < 0.0 || z < 0.0 {
+            return None;
+        }
+
+        let coords = UVec2::new(x as u32, z as u32);
+        self.index(coords).map(|_| coords)
+    }
+
+    // Center of the top of a tile
+    pub fn tile_center(&self, coords: UVec2) -> Vec3 {
+        let half_size = self.size() / 2.0;
+        let height = self.get(coords).map(|tile| tile.height).unwrap_or_default();
+        Vec3::new(
+            (coords.x as f32 + 0.5) * self.tile_size - half_size.x,
+            height,
+            (coords.y as f32 + 0.5) * self.tile_size - half_size.y,
+        )
+    }
+
+    pub fn tile_at(&self, position: Vec3) -> Option<&Tile> {
+        self.get(self.tile_coords(position)?)
+    }
+
+    pub fn surface_height(&self, position: Vec3) -> Option<f32> {
+        self.tile_at(position).map(|tile| tile.height)
+    }
+
+    pub fn is_walkable(&self, position: Vec3) -> bool {
+        self.tile_at(position).is_some_and(Tile::is_walkable)
+    }
+
+    // Whether a player can move from one position to another in a single step
+    pub fn can_step(&self, from: Vec3, to: Vec3) -> bool {
+        let Some(to_tile) = self.tile_at(to) else {
+            return false;
+        };
+        if !to_tile.is_walkable() {
+            return false;
+        }
+
+        // Stairs connect any heights, and a player stuck in a wall or prop can always step out
+        match self.tile_at(from) {
+            Some(from_tile) if from_tile.kind == TileKind::Floor => {
+                to_tile.kind == TileKind::Stairs
+                    || (to_tile.height - from_tile.height).abs() <= MAX_STEP_HEIGHT
+            }
+            _ => true,
+        }
+    }
+
+    // Clamp a position inside the map, resting height_above over the surface
+    pub fn clamp(&self, position: Vec3, height_above: f32) -> Vec3 {
+        let half_size = self.size() / 2.0 - EDGE_MARGIN;
+        let mut position = Vec3::new(
+            position.x.clamp(-half_size.x, half_size.x),
+            position.y,
+            position.z.clamp(-half_size.y, half_size.y),
+        );
+        position.y = self.surface_height(position).unwrap_or_default() + height_above;
+        position
+    }
+
+    // Move towards a position, sliding along walls, water and ledges instead of entering them
+    pub fn constrain_move(&self, from: Vec3, to: Vec3, height_above: f32) -> Vec3 {
+        let to = self.clamp(to, height_above);
+        let candidates = [
+            to,
+            Vec3::new(to.x, from.y, from.z),
+            Vec3::new(from.x, from.y, to.z),
+        ];
+
+        let position = candidates
+            .into_iter()
+            .find(|&candidate| self.can_step(from, candidate))
+            .unwrap_or(from);
+        self.clamp(position, height_above)
+    }
+
+    pub fn walkable_tiles(&self) -> impl Iterator<Item = UVec2> + '_ {
+        self.iter()
+            .filter(|(_, tile)| tile.is_walkable())
+            .map(|(coords, _)| coords)
+    }
+
+    // Random position on a walkable tile, resting height_above over its surface
+    pub fn random_walkable_position(&self, height_above: f32) -> Option<Vec3> {
+        let walkable: Vec<UVec2> = self.walkable_tiles().collect();
+        if walkable.is_empty() {
+            return None;
+        }
+
+        let mut rng = rand::thread_rng();
+        let coords = walkable[rng.gen_range(0..walkable.len())];
+        let half_tile = self.tile_size / 2.0 - EDGE_MARGIN;
+        let offset = Vec3::new(
+            rng.gen_range(-half_tile..half_tile),
+            height_above,
+            rng.gen_range(-half_tile..half_tile),
+        );
+
+        Some(self.tile_center(coords) + offset)
+    }
+
+    // First tile the ray hits, and where it hits the tile's top or side
+    pub fn raycast(&self, origin: Vec3, direction: Vec3) -> Option<(UVec2, Vec3)> {
+        let direction = direction.normalize_or_zero();
+        if direction.y >= 0.0 {
+            return None;
+        }
+
+        let (min_height, max_height) = self
+            .tiles
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), tile| {
+                (min.min(tile.height), max.max(tile.height))
+            });
+
+        // Only the part of the ray between the highest and lowest tops can hit anything
+        let start = ((origin.y - max_height) / -direction.y).max(0.0);
+        let end = (origin.y - min_height) / -direction.y;
+        let step = self.tile_size * RAY_STEP;
+
+        let is_below_surface = |distance: f32| {
+            let point = origin + direction * distance;
+            self.surface_height(point)
+                .is_some_and(|height| point.y <= height)
+        };
+
+        let mut previous = start;
+        let mut distance = start;
+        while distance <= end + step {
+            if is_below_surface(distance) {
+                let (mut above, mut below) = (previous, distance);
+                for _ in 0..RAY_REFINE_STEPS {
+                    let middle = (above + below) / 2.0;
+                    if is_below_surface(middle) {
+                        below = middle;
+                    } else {
+                        above = middle;
+                    }
+                }
+
+                let point = origin + direction * below;
+                return Some((self.tile_coords(point)?, point));
+            }
+            previous = distance;
+            distance += step;
+        }
+
+        None
+    }
+}
+
+// Tiles sharing an edge with a tile. Wrapping keeps neighbours past the -x/-z edges out of
+// range, so `TileMap::get` returns None for them like for those past the +x/+z edges.
+pub fn neighbours(coords: UVec2) -> [UVec2; 4] {
+    [
+        UVec2::new(coords.x.wrapping_sub(1), coords.y),
+        UVec2::new(coords.x + 1, coords.y),
+        UVec2::new(coords.x, coords.y.wrapping_sub(1)),
+        UVec2::new(coords.x, coords.y + 1),
+    ]
+}
@@ -0,0 +1,138 @@
+use bevy::asset::AssetPlugin;
+use bevy::prelude::*;
+use bevy::text::Font;
+use iso::dev_server;
+use iso::player::store::PlayerStore;
+use iso::player::systems::FriendTag;
+use iso::player::PlayerPlugin;
+use iso::socket::client::SocketStatus;
+use iso::socket::{Config as SocketConfig, Socket, SocketPlugin};
+use iso::terrain::TerrainPlugin;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+/// Shared harness for integration tests: an in-process dev server, plus headless apps
+/// running `SocketPlugin` and `PlayerPlugin` without a window or renderer.
+
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+const UPDATE_INTERVAL: Duration = Duration::from_millis(10);
+
+pub struct TestServer {
+    pub url: String,
+    runtime: Option<Runtime>,
+}
+
+impl TestServer {
+    // Start the dev server on a free local port, returning once it accepts connections
+    pub fn start() -> Self {
+        Self::start_at(free_address())
+    }
+
+    pub fn start_at(address: SocketAddr) -> Self {
+        let runtime = Runtime::new().unwrap();
+        runtime.spawn(async move {
+            if let Err(e) = dev_server::run(&address.to_string()).await {
+                panic!("test server stopped: {e}");
+            }
+        });
+
+        let started_at = Instant::now();
+        while TcpStream::connect(address).is_err() {
+            assert!(started_at.elapsed() < TIMEOUT, "test server did not start");
+            thread::sleep(UPDATE_INTERVAL);
+        }
+
+        Self {
+            url: format!("ws://{address}"),
+            runtime: Some(runtime),
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        // Don't wait on open sessions, the test is over
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+// Local address nothing is listening on, yet
+pub fn free_address() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("no free port for the test server")
+}
+
+// Build a headless app connected to the given server
+pub fn build_app(url: &str) -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+        .init_asset::<Mesh>()
+        .init_asset::<StandardMaterial>()
+        .init_asset::<Font>()
+        .init_resource::<ButtonInput<KeyCode>>()
+        .add_plugins(SocketPlugin {
+            config: SocketConfig {
+                url: Some(url.to_string()),
+                ..default()
+            },
+        })
+        .add_plugins(TerrainPlugin::default())
+        .add_plugins(PlayerPlugin::default());
+    app
+}
+
+// Update the app until the condition holds, returning false if it never does within TIMEOUT
+pub fn update_until(app: &mut App, condition: impl Fn(&mut App) -> bool) -> bool {
+    let started_at = Instant::now();
+    while started_at.elapsed() < TIMEOUT {
+        app.update();
+        if condition(app) {
+            return true;
+        }
+        thread::sleep(UPDATE_INTERVAL);
+    }
+    false
+}
+
+// Update both apps until the condition holds for the pair
+pub fn update_both_until(
+    a: &mut App,
+    b: &mut App,
+    condition: impl Fn(&mut App, &mut App) -> bool,
+) -> bool {
+    let started_at = Instant::now();
+    while started_at.elapsed() < TIMEOUT {
+        a.update();
+        b.update();
+        if condition(a, b) {
+            return true;
+        }
+        thread::sleep(UPDATE_INTERVAL);
+    }
+    false
+}
+
+pub fn is_connected(app: &App) -> bool {
+    app.world.resource::<Socket>().status == Some(SocketStatus::Connected)
+}
+
+pub fn player_uuid(app: &App) -> String {
+    app.world.resource::<PlayerStore>().player_uuid.clone()
+}
+
+pub fn friend_translation(app: &mut App, player_uuid: &str) -> Option<Vec3> {
+    app.world
+        .query::<(&FriendTag, &Transform)>()
+        .iter(&app.world)
+        .find(|(friend_tag, _)| friend_tag.player_uuid == player_uuid)
+        .map(|(_, transform)| transform.translation)
+}
+
+pub fn has_friend(app: &mut App, player_uuid: &str) -> bool {
+    friend_translation(app, player_uuid).is_some()
+}
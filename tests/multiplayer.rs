@@ -0,0 +1,151 @@
+mod common;
+
+use bevy::prelude::*;
+use common::{
+    build_app, free_address, friend_translation, has_friend, is_connected, player_uuid,
+    update_both_until, update_until, TestServer,
+};
+use iso::player::store::PlayerStore;
+use iso::socket::client::SocketStatus;
+use iso::socket::request::Request;
+use iso::socket::{Socket, GAME_ROOM};
+use iso::terrain::tile_map::TileMap;
+
+// Connect two apps to the same server and wait until each has spawned the other
+fn connect_pair(server: &TestServer) -> (App, App) {
+    let mut a = build_app(&server.url);
+    assert!(
+        update_until(&mut a, |a| is_connected(a)),
+        "a never connected"
+    );
+
+    let mut b = build_app(&server.url);
+    let (a_uuid, b_uuid) = (player_uuid(&a), player_uuid(&b));
+    assert!(
+        update_both_until(&mut a, &mut b, |a, b| {
+            has_friend(a, &b_uuid) && has_friend(b, &a_uuid)
+        }),
+        "friends were never spawned"
+    );
+
+    (a, b)
+}
+
+#[test]
+fn join_spawns_friends_from_presence_state_and_diff() {
+    let server = TestServer::start();
+    let (a, b) = connect_pair(&server);
+
+    // b learned about a from presence_state, a learned about b from presence_diff
+    let a_store = a.world.resource::<PlayerStore>();
+    let b_store = b.world.resource::<PlayerStore>();
+    assert!(a_store.get_friend(&b_store.player_uuid).is_some());
+    assert!(b_store.get_friend(&a_store.player_uuid).is_some());
+    assert_eq!(a_store.get_friends().len(), 1);
+    assert_eq!(b_store.get_friends().len(), 1);
+}
+
+#[test]
+fn player_update_moves_friend() {
+    let server = TestServer::start();
+    let (mut a, mut b) = connect_pair(&server);
+    let b_uuid = player_uuid(&b);
+
+    // A short step towards the center of b's tile, within the speed limit and the same tile
+    let current = b
+        .world
+        .resource::<PlayerStore>()
+        .get_player()
+        .position
+        .unwrap();
+    let tile_map = b.world.resource::<TileMap>();
+    let tile_center = tile_map.tile_center(tile_map.tile_coords(current).unwrap());
+    let to_center = Vec3::new(tile_center.x - current.x, 0.0, tile_center.z - current.z);
+    let position = current + to_center.clamp_length_max(0.05);
+    let request = Request::new_player_update(GAME_ROOM.to_string(), b_uuid.clone(), position, 0);
+    b.world
+        .resource::<Socket>()
+        .handle
+        .call(request)
+        .expect("player_update request error");
+
+    assert!(
+        update_both_until(&mut a, &mut b, |a, _| {
+            friend_translation(a, &b_uuid) == Some(position)
+        }),
+        "friend never moved"
+    );
+    let friend = a.world.resource::<PlayerStore>().get_friend(&b_uuid);
+    assert_eq!(friend.and_then(|friend| friend.position), Some(position));
+}
+
+#[test]
+fn leave_despawns_friend() {
+    let server = TestServer::start();
+    let (mut a, b) = connect_pair(&server);
+    let b_uuid = player_uuid(&b);
+
+    b.world
+        .resource::<Socket>()
+        .handle
+        .close(None)
+        .expect("close error");
+    drop(b);
+
+    assert!(
+        update_until(&mut a, |a| !has_friend(a, &b_uuid)),
+        "friend was never despawned"
+    );
+    let a_store = a.world.resource::<PlayerStore>();
+    assert!(a_store.get_friend(&b_uuid).is_none());
+    assert!(a_store.get_friends().is_empty());
+}
+
+#[test]
+fn forced_reconnect_rejoins_the_room() {
+    let server = TestServer::start();
+    let (mut a, mut b) = connect_pair(&server);
+    let (a_uuid, b_uuid) = (player_uuid(&a), player_uuid(&b));
+
+    // Like a stale connection found by the heartbeat timer
+    let mut socket = b.world.resource_mut::<Socket>();
+    socket.status = Some(SocketStatus::Stale);
+    socket.reconnect();
+
+    // b's friends are cleared on connecting, and come back with the room's presence_state
+    assert!(
+        update_both_until(&mut a, &mut b, |a, b| {
+            let (a_store, b_store) = (
+                a.world.resource::<PlayerStore>(),
+                b.world.resource::<PlayerStore>(),
+            );
+            is_connected(b)
+                && a_store.get_friend(&b_uuid).is_some()
+                && b_store.get_friend(&a_uuid).is_some()
+        }),
+        "never rejoined after reconnecting"
+    );
+    assert_eq!(b.world.resource::<PlayerStore>().get_friends().len(), 1);
+}
+
+#[test]
+fn failed_connection_is_retried_until_the_server_is_up() {
+    let address = free_address();
+    let mut app = build_app(&format!("ws://{address}"));
+    fn socket(app: &App) -> &Socket {
+        app.world.resource::<Socket>()
+    }
+
+    assert!(
+        update_until(&mut app, |app| socket(app).reconnect_attempts >= 1),
+        "never scheduled a reconnect"
+    );
+    assert_eq!(socket(&app).status, Some(SocketStatus::ConnectFailed));
+
+    let _server = TestServer::start_at(address);
+    assert!(
+        update_until(&mut app, |app| is_connected(app)),
+        "never reconnected"
+    );
+    assert_eq!(socket(&app).reconnect_attempts, 0);
+}